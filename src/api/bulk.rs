@@ -0,0 +1,313 @@
+use std::collections::HashSet;
+
+use axum::{Router, extract::State, routing::post};
+use post_archiver::{
+    AuthorId, CollectionId, Content, FileMetaId, PlatformId, Post, PostId, TagId,
+    manager::{PostArchiverManager, UpdatePost},
+};
+use rusqlite::{Connection, params};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use super::{
+    AppState,
+    category::Category,
+    error::{ApiError, ApiResult},
+    extract::Json,
+    filter::PostFilter,
+    history,
+    post::{DeletedPost, RemovedFile, delete_post},
+};
+
+pub fn wrap_bulk_route(router: Router<AppState>) -> Router<AppState> {
+    router.route("/posts/bulk", post(bulk_post_handler))
+}
+
+#[derive(Debug, Deserialize, TS)]
+#[ts(export)]
+pub struct BulkPayload {
+    #[serde(default)]
+    pub ids: Vec<PostId>,
+    #[serde(default)]
+    pub filter: Option<PostFilter>,
+    // confirms that a filter without any criteria may delete every post
+    #[serde(default)]
+    pub all: bool,
+    pub operation: BulkOperation,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+impl BulkPayload {
+    // ids win over the filter, the same way the handler selects posts
+    fn validate(&self) -> ApiResult<()> {
+        match &self.filter {
+            _ if !self.ids.is_empty() => Ok(()),
+            None => {
+                Err(ApiError::bad_request("either ids or filter is required").with_field("ids"))
+            }
+            Some(filter) if self.operation.is_destructive() && filter.is_empty() && !self.all => {
+                Err(
+                    ApiError::bad_request("the filter matches every post, set all to confirm")
+                        .with_field("filter"),
+                )
+            }
+            Some(_) => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, TS)]
+#[serde(tag = "op", rename_all = "snake_case")]
+#[ts(export)]
+pub enum BulkOperation {
+    AddTags {
+        ids: Vec<TagId>,
+    },
+    RemoveTags {
+        ids: Vec<TagId>,
+    },
+    AddAuthors {
+        ids: Vec<AuthorId>,
+    },
+    RemoveAuthors {
+        ids: Vec<AuthorId>,
+    },
+    AddCollections {
+        ids: Vec<CollectionId>,
+    },
+    RemoveCollections {
+        ids: Vec<CollectionId>,
+    },
+    SetPlatform {
+        platform: Option<PlatformId>,
+    },
+    ThumbFromFirstImage,
+    Delete {
+        #[serde(default)]
+        cascade: bool,
+    },
+}
+
+impl BulkOperation {
+    fn is_destructive(&self) -> bool {
+        matches!(self, BulkOperation::Delete { .. })
+    }
+}
+
+#[derive(Debug, Serialize, TS)]
+#[ts(export)]
+pub struct BulkReport {
+    pub results: Vec<BulkResult>,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub dry_run: bool,
+    pub files: Vec<RemovedFile>,
+    pub bytes_freed: u64,
+}
+
+#[derive(Debug, Serialize, TS)]
+#[ts(export)]
+pub struct BulkResult {
+    pub id: PostId,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ApiError>,
+}
+
+async fn bulk_post_handler(
+    State(state): State<AppState>,
+    Json(payload): Json<BulkPayload>,
+) -> ApiResult<Json<BulkReport>> {
+    payload.validate()?;
+    let BulkPayload {
+        ids,
+        filter,
+        operation,
+        dry_run,
+        ..
+    } = payload;
+
+    let (report, deleted) = state
+        .transaction(move |manager| {
+            let ids = match filter {
+                Some(filter) if ids.is_empty() => filter.select(manager, None)?,
+                _ => dedup_ids(ids),
+            };
+
+            let conn = manager.conn();
+            // a dry run applies everything and rolls it back to build the same report
+            conn.execute_batch("SAVEPOINT bulk")?;
+
+            let mut results = Vec::with_capacity(ids.len());
+            let mut deleted = DeletedPost::default();
+            for id in ids {
+                // each post runs in a savepoint so one failure leaves the others applied
+                conn.execute_batch("SAVEPOINT bulk_post")?;
+                let error = match apply(manager, id, &operation) {
+                    Ok(removed) => {
+                        deleted.extend(removed);
+                        None
+                    }
+                    Err(err) => {
+                        conn.execute_batch("ROLLBACK TO bulk_post")?;
+                        Some(err)
+                    }
+                };
+                conn.execute_batch("RELEASE bulk_post")?;
+                results.push(BulkResult {
+                    id,
+                    ok: error.is_none(),
+                    error,
+                });
+            }
+
+            if dry_run {
+                conn.execute_batch("ROLLBACK TO bulk")?;
+            }
+            conn.execute_batch("RELEASE bulk")?;
+
+            let succeeded = results.iter().filter(|result| result.ok).count();
+            let report = BulkReport {
+                failed: results.len() - succeeded,
+                succeeded,
+                results,
+                dry_run,
+                bytes_freed: deleted.files.iter().map(|file| file.size).sum(),
+                files: deleted.files.clone(),
+            };
+            Ok((report, deleted))
+        })
+        .await?;
+
+    if !report.dry_run {
+        deleted.apply_to_disk(state.path()).await;
+    }
+    Ok(Json(report))
+}
+
+fn dedup_ids(ids: Vec<PostId>) -> Vec<PostId> {
+    let mut seen = HashSet::new();
+    ids.into_iter().filter(|id| seen.insert(*id)).collect()
+}
+
+fn apply(
+    manager: &PostArchiverManager,
+    id: PostId,
+    operation: &BulkOperation,
+) -> ApiResult<DeletedPost> {
+    let post = manager
+        .get_post(id)?
+        .ok_or_else(|| ApiError::not_found(Post::ROUTE, id.0))?;
+
+    if let BulkOperation::Delete { cascade } = operation {
+        return delete_post(manager, id, *cascade);
+    }
+
+    history::record::<Post>(manager, id.0, "bulk")?;
+
+    let bound = manager.bind(id);
+    match operation {
+        BulkOperation::AddTags { ids } => {
+            let current = bound.list_tags()?;
+            bound.add_tags(&missing_from(ids, &current))?;
+        }
+        BulkOperation::RemoveTags { ids } => bound.remove_tags(ids)?,
+        BulkOperation::AddAuthors { ids } => {
+            let current = bound.list_authors()?;
+            bound.add_authors(&missing_from(ids, &current))?;
+        }
+        BulkOperation::RemoveAuthors { ids } => bound.remove_authors(ids)?,
+        BulkOperation::AddCollections { ids } => {
+            let current = bound.list_collections()?;
+            bound.add_collections(&missing_from(ids, &current))?;
+        }
+        BulkOperation::RemoveCollections { ids } => bound.remove_collections(ids)?,
+        BulkOperation::SetPlatform { platform } => {
+            bound.update(UpdatePost::default().platform(*platform))?
+        }
+        BulkOperation::ThumbFromFirstImage => {
+            let thumb = first_image(manager.conn(), &post.content, id)?
+                .ok_or_else(|| ApiError::bad_request(format!("post {} has no image", id.0)))?;
+            bound.update(UpdatePost::default().thumb(Some(thumb)))?
+        }
+        BulkOperation::Delete { .. } => unreachable!("handled above"),
+    }
+    Ok(DeletedPost::default())
+}
+
+fn missing_from<T: Copy + PartialEq>(ids: &[T], current: &[T]) -> Vec<T> {
+    ids.iter()
+        .copied()
+        .filter(|id| !current.contains(id))
+        .collect()
+}
+
+// prefer the first image shown in the content, then the first image uploaded
+fn first_image(
+    conn: &Connection,
+    content: &[Content],
+    post: PostId,
+) -> rusqlite::Result<Option<FileMetaId>> {
+    let mut is_image = conn.prepare_cached(
+        "SELECT EXISTS (SELECT 1 FROM file_metas WHERE id = ?1 AND post = ?2 AND mime LIKE 'image/%')",
+    )?;
+    for item in content {
+        if let Content::File(file) = item
+            && is_image.query_row(params![file.0, post.0], |row| row.get(0))?
+        {
+            return Ok(Some(*file));
+        }
+    }
+
+    let mut stmt = conn.prepare_cached(
+        "SELECT id FROM file_metas WHERE post = ?1 AND mime LIKE 'image/%' ORDER BY id LIMIT 1",
+    )?;
+    let mut rows = stmt.query_map(params![post.0], |row| row.get(0).map(FileMetaId))?;
+    rows.next().transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::BulkPayload;
+
+    fn validate(payload: Value) -> Result<(), Option<String>> {
+        let payload: BulkPayload = serde_json::from_value(payload).unwrap();
+        payload.validate().map_err(|err| err.field)
+    }
+
+    #[test]
+    fn deleting_everything_needs_confirmation() {
+        let delete = json!({ "op": "delete" });
+        let tag = json!({ "op": "add_tags", "ids": [1] });
+
+        assert_eq!(
+            validate(json!({ "operation": delete })),
+            Err(Some("ids".to_string()))
+        );
+        assert_eq!(
+            validate(json!({ "filter": {}, "operation": delete })),
+            Err(Some("filter".to_string()))
+        );
+        assert_eq!(
+            validate(json!({ "filter": { "sort": "title" }, "operation": delete })),
+            Err(Some("filter".to_string()))
+        );
+
+        assert_eq!(
+            validate(json!({ "filter": {}, "all": true, "operation": delete })),
+            Ok(())
+        );
+        assert_eq!(
+            validate(json!({ "filter": { "tag": [1] }, "operation": delete })),
+            Ok(())
+        );
+        assert_eq!(
+            validate(json!({ "ids": [1], "filter": {}, "operation": delete })),
+            Ok(())
+        );
+        assert_eq!(validate(json!({ "filter": {}, "operation": tag })), Ok(()));
+    }
+}
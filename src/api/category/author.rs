@@ -1,29 +1,39 @@
 use axum::{
-    Json, Router,
-    extract::{Path, State},
+    Router,
+    extract::State,
     http::StatusCode,
-    routing::get,
+    routing::{get, post},
 };
 use chrono::{DateTime, Utc};
 use optional_field::Field;
 use post_archiver::{
     Alias, Author, AuthorId, FileMetaId, PlatformId,
+    importer::{UnsyncAlias, UnsyncAuthor},
     manager::{PostArchiverManager, UpdateAuthor},
-    query::{Countable, Paginate, Query, SortDir, Sortable, Totalled, author::AuthorSort},
+    query::{Countable, Paginate, Query, Sortable, Totalled, author::AuthorSort},
 };
+use rusqlite::{OptionalExtension, params};
 use serde::{Deserialize, Serialize};
+use ts_rs::TS;
 
 use crate::api::{
     AppState,
     category::{
-        delete_category_handler, get_category_handler, list_category_handler,
-        list_category_posts_handler, update_category_handler,
+        create_category_handler, delete_category_handler, get_category_handler,
+        list_category_handler, list_category_posts_handler, merge_category_handler,
+        update_category_handler,
     },
+    error::{ApiError, ApiResult},
+    extract::{Json, Path},
+    history::{self, Tracked},
     relation::{RequireRelations, WithRelations},
     utils::Pagination,
 };
 
-use super::{Category, UpdateCategoryPayload};
+use super::{
+    Category, CategorySort, CreateCategoryPayload, Filter, UpdateCategoryPayload, already_exists,
+    list_by_usage, merge_links, merge_thumb,
+};
 
 impl RequireRelations for Author {
     fn file_metas(&self) -> Vec<FileMetaId> {
@@ -31,25 +41,54 @@ impl RequireRelations for Author {
     }
 }
 
+impl Tracked for Author {
+    const KIND: &'static str = "authors";
+    const TABLE: &'static str = "authors";
+    const LINKS: &'static [(&'static str, &'static str)] =
+        &[("author_posts", "author"), ("author_aliases", "target")];
+}
+
 impl Category for Author {
     type Id = AuthorId;
     type UpdatePayload = UpdateAuthorPayload;
+    type CreatePayload = CreateAuthorPayload;
 
     const ROUTE: &'static str = "authors";
+    const POSTS: (&'static str, &'static str) = ("author_posts", "author");
+
+    fn raw_id(&self) -> u32 {
+        self.id.0
+    }
 
     fn list_query(
         manager: &PostArchiverManager,
         pagination: &Pagination,
-        search: &str,
-    ) -> post_archiver::error::Result<Totalled<Vec<Self>>> {
+        filter: &Filter,
+    ) -> ApiResult<Totalled<Vec<Self>>> {
+        let sort = match filter.sort {
+            CategorySort::Id => AuthorSort::Id,
+            CategorySort::Name => AuthorSort::Name,
+            CategorySort::Updated => AuthorSort::Updated,
+            CategorySort::Count => return list_by_usage::<Self>(manager, pagination, filter),
+        };
+
         let mut q = manager.authors();
-        if !search.is_empty() {
-            q.name.contains(search);
+        if !filter.search.is_empty() {
+            q.name.contains(&filter.search);
         }
-        q.sort(AuthorSort::Id, SortDir::Desc)
+        Ok(q.sort(sort, filter.dir.into())
             .pagination(pagination.limit(), pagination.page())
             .with_total()
-            .query::<Author>()
+            .query::<Author>()?)
+    }
+
+    fn sort_column(sort: CategorySort) -> Option<&'static str> {
+        match sort {
+            CategorySort::Id => Some("t.id"),
+            CategorySort::Name => Some("t.name COLLATE NOCASE"),
+            CategorySort::Updated => Some("julianday(t.updated)"),
+            CategorySort::Count => None,
+        }
     }
 
     fn get_single(
@@ -59,18 +98,33 @@ impl Category for Author {
         manager.get_author(id)
     }
 
-    fn delete_entity(
+    fn delete_entity(manager: &PostArchiverManager, id: Self::Id) -> ApiResult<()> {
+        Ok(manager.bind(id).delete()?)
+    }
+
+    fn merge_entities(
         manager: &PostArchiverManager,
-        id: Self::Id,
-    ) -> post_archiver::error::Result<()> {
-        manager.bind(id).delete()
+        target: Self::Id,
+        sources: &[Self::Id],
+    ) -> ApiResult<()> {
+        let conn = manager.conn();
+        for &source in sources {
+            merge_links(conn, "author_posts", "author", target.0, source.0)?;
+            conn.execute(
+                "UPDATE author_aliases SET target = ?1 WHERE target = ?2",
+                params![target.0, source.0],
+            )?;
+            merge_thumb(conn, "authors", target.0, source.0)?;
+            manager.bind(source).delete()?;
+        }
+        Ok(())
     }
 
     fn wrap_category_route(router: Router<AppState>) -> Router<AppState> {
-        router
+        Self::wrap_history_route(router, &format!("/{}", Self::ROUTE))
             .route(
                 &format!("/{}", Self::ROUTE),
-                get(list_category_handler::<Self>),
+                get(list_category_handler::<Self>).post(create_category_handler::<Self>),
             )
             .route(
                 &format!("/{}/{{id}}", Self::ROUTE),
@@ -82,6 +136,10 @@ impl Category for Author {
                 &format!("/{}/{{id}}/posts", Self::ROUTE),
                 get(list_category_posts_handler::<Self>),
             )
+            .route(
+                &format!("/{}/{{id}}/merge", Self::ROUTE),
+                post(merge_category_handler::<Self>),
+            )
             .route(
                 &format!("/{}/{{id}}/aliases", Self::ROUTE),
                 get(author_aliases_handler).patch(update_author_aliases_handler),
@@ -100,22 +158,23 @@ impl Category for Author {
 pub async fn author_aliases_handler(
     State(state): State<AppState>,
     Path(id): Path<AuthorId>,
-) -> Result<Json<WithRelations<Totalled<Vec<Alias>>>>, StatusCode> {
-    let manager = &state.manager();
-    let list = manager
-        .bind(id)
-        .list_aliases()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    WithRelations::new(
-        manager,
-        Totalled {
-            total: list.len() as u64,
-            items: list,
-        },
-    )
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
-    .map(Json::from)
+) -> ApiResult<Json<WithRelations<Totalled<Vec<Alias>>>>> {
+    state
+        .read(move |manager| {
+            manager
+                .get_author(id)?
+                .ok_or_else(|| ApiError::not_found(Author::ROUTE, id.0))?;
+            let list = manager.bind(id).list_aliases()?;
+
+            Ok(Json(WithRelations::new(
+                manager,
+                Totalled {
+                    total: list.len() as u64,
+                    items: list,
+                },
+            )?))
+        })
+        .await
 }
 
 impl RequireRelations for Alias {
@@ -132,11 +191,7 @@ pub struct UpdateAuthorPayload {
 }
 
 impl UpdateCategoryPayload<AuthorId> for UpdateAuthorPayload {
-    fn apply(
-        self,
-        manager: &PostArchiverManager,
-        id: AuthorId,
-    ) -> post_archiver::error::Result<()> {
+    fn apply(self, manager: &PostArchiverManager, id: AuthorId) -> ApiResult<()> {
         let mut update = UpdateAuthor::default();
         if let Some(name) = self.name {
             update = update.name(name);
@@ -147,7 +202,63 @@ impl UpdateCategoryPayload<AuthorId> for UpdateAuthorPayload {
         if let Some(updated) = self.updated {
             update = update.updated(updated);
         }
-        manager.bind(id).update(update)
+        manager.bind(id).update(update)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, TS)]
+#[ts(export)]
+pub struct CreateAuthorPayload {
+    pub name: String,
+    #[serde(default)]
+    pub thumb: Option<FileMetaId>,
+    #[serde(default)]
+    pub updated: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub aliases: Vec<Alias>,
+}
+
+impl CreateCategoryPayload<AuthorId> for CreateAuthorPayload {
+    fn create(self, manager: &PostArchiverManager) -> ApiResult<AuthorId> {
+        // the importer would merge into the author owning one of the aliases
+        for alias in &self.aliases {
+            let owner: Option<u32> = manager
+                .conn()
+                .query_row(
+                    "SELECT target FROM author_aliases WHERE source = ?1",
+                    params![alias.source],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            if owner.is_some() {
+                return Err(already_exists("author alias", "source", &alias.source));
+            }
+        }
+
+        let aliases = self
+            .aliases
+            .into_iter()
+            .map(|alias| {
+                let unsync = UnsyncAlias::new(alias.platform, alias.source);
+                match alias.link {
+                    Some(link) => unsync.link(link),
+                    None => unsync,
+                }
+            })
+            .collect();
+        let id = manager.import_author(
+            UnsyncAuthor::new(self.name)
+                .updated(self.updated)
+                .aliases(aliases),
+        )?;
+
+        if let Some(thumb) = self.thumb {
+            manager
+                .bind(id)
+                .update(UpdateAuthor::default().thumb(Some(thumb)))?;
+        }
+        Ok(id)
     }
 }
 
@@ -160,34 +271,35 @@ async fn update_author_aliases_handler(
     Path(id): Path<AuthorId>,
     State(state): State<AppState>,
     Json(payload): Json<UpdateAuthorAliasesPayload>,
-) -> Result<StatusCode, StatusCode> {
-    let manager = state.manager();
-
-    let bound = manager.bind(id);
-
-    let new_aliases = payload.items;
-    let current = manager
-        .bind(id)
-        .list_aliases()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    let to_remove: Vec<_> = current
-        .iter()
-        .filter(|&a| !new_aliases.contains(a))
-        .cloned()
-        .map(|a| (a.source, a.platform))
-        .collect();
-    let to_add: Vec<_> = new_aliases
-        .iter()
-        .filter(|&a| !current.contains(a))
-        .cloned()
-        .map(|a| (a.source, a.platform, a.link))
-        .collect();
-    bound
-        .remove_aliases(&to_remove)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    bound
-        .add_aliases(to_add)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+) -> ApiResult<StatusCode> {
+    state
+        .transaction(move |manager| {
+            manager
+                .get_author(id)?
+                .ok_or_else(|| ApiError::not_found(Author::ROUTE, id.0))?;
+            history::record::<Author>(manager, id.0, "update")?;
+
+            let bound = manager.bind(id);
+
+            let new_aliases = payload.items;
+            let current = manager.bind(id).list_aliases()?;
+            let to_remove: Vec<_> = current
+                .iter()
+                .filter(|&a| !new_aliases.contains(a))
+                .cloned()
+                .map(|a| (a.source, a.platform))
+                .collect();
+            let to_add: Vec<_> = new_aliases
+                .iter()
+                .filter(|&a| !current.contains(a))
+                .cloned()
+                .map(|a| (a.source, a.platform, a.link))
+                .collect();
+            bound.remove_aliases(&to_remove)?;
+            bound.add_aliases(to_add)?;
+            Ok(())
+        })
+        .await?;
 
     Ok(StatusCode::NO_CONTENT)
 }
@@ -1,13 +1,20 @@
-use crate::api::{relation::RequireRelations, utils::Pagination};
+use crate::api::{
+    error::ApiResult, history::Tracked, relation::RequireRelations, utils::Pagination,
+};
 use optional_field::{Field, serde_optional_fields};
 use post_archiver::{
     Collection, CollectionId, FileMetaId,
+    importer::UnsyncCollection,
     manager::{PostArchiverManager, UpdateCollection},
-    query::{Countable, Paginate, Query, SortDir, Sortable, Totalled, collection::CollectionSort},
+    query::{Countable, Paginate, Query, Sortable, Totalled, collection::CollectionSort},
 };
 use serde::{Deserialize, Serialize};
+use ts_rs::TS;
 
-use super::{Category, UpdateCategoryPayload};
+use super::{
+    Category, CategorySort, CreateCategoryPayload, Filter, UpdateCategoryPayload, already_exists,
+    list_by_usage, merge_links, merge_thumb, pending_source,
+};
 
 impl RequireRelations for Collection {
     fn file_metas(&self) -> Vec<FileMetaId> {
@@ -15,25 +22,44 @@ impl RequireRelations for Collection {
     }
 }
 
+impl Tracked for Collection {
+    const KIND: &'static str = "collections";
+    const TABLE: &'static str = "collections";
+    const LINKS: &'static [(&'static str, &'static str)] = &[("collection_posts", "collection")];
+}
+
 impl Category for Collection {
     type Id = CollectionId;
     type UpdatePayload = UpdateCollectionPayload;
+    type CreatePayload = CreateCollectionPayload;
 
     const ROUTE: &'static str = "collections";
+    const POSTS: (&'static str, &'static str) = ("collection_posts", "collection");
+
+    fn raw_id(&self) -> u32 {
+        self.id.0
+    }
 
     fn list_query(
         manager: &PostArchiverManager,
         pagination: &Pagination,
-        search: &str,
-    ) -> post_archiver::error::Result<Totalled<Vec<Self>>> {
+        filter: &Filter,
+    ) -> ApiResult<Totalled<Vec<Self>>> {
+        let sort = match filter.sort {
+            CategorySort::Id => CollectionSort::Id,
+            CategorySort::Name => CollectionSort::Name,
+            CategorySort::Updated => return Err(filter.unsupported_sort::<Self>()),
+            CategorySort::Count => return list_by_usage::<Self>(manager, pagination, filter),
+        };
+
         let mut q = manager.collections();
-        if !search.is_empty() {
-            q.name.contains(search);
+        if !filter.search.is_empty() {
+            q.name.contains(&filter.search);
         }
-        q.sort(CollectionSort::Id, SortDir::Desc)
+        Ok(q.sort(sort, filter.dir.into())
             .pagination(pagination.limit(), pagination.page())
             .with_total()
-            .query::<Collection>()
+            .query::<Collection>()?)
     }
 
     fn get_single(
@@ -43,11 +69,22 @@ impl Category for Collection {
         manager.get_collection(id)
     }
 
-    fn delete_entity(
+    fn delete_entity(manager: &PostArchiverManager, id: Self::Id) -> ApiResult<()> {
+        Ok(manager.bind(id).delete()?)
+    }
+
+    fn merge_entities(
         manager: &PostArchiverManager,
-        id: Self::Id,
-    ) -> post_archiver::error::Result<()> {
-        manager.bind(id).delete()
+        target: Self::Id,
+        sources: &[Self::Id],
+    ) -> ApiResult<()> {
+        let conn = manager.conn();
+        for &source in sources {
+            merge_links(conn, "collection_posts", "collection", target.0, source.0)?;
+            merge_thumb(conn, "collections", target.0, source.0)?;
+            manager.bind(source).delete()?;
+        }
+        Ok(())
     }
 
     fn filter_posts<T>(
@@ -68,11 +105,7 @@ pub struct UpdateCollectionPayload {
 }
 
 impl UpdateCategoryPayload<CollectionId> for UpdateCollectionPayload {
-    fn apply(
-        self,
-        manager: &PostArchiverManager,
-        id: CollectionId,
-    ) -> post_archiver::error::Result<()> {
+    fn apply(self, manager: &PostArchiverManager, id: CollectionId) -> ApiResult<()> {
         let mut update = UpdateCollection::default();
         if let Some(name) = self.name {
             update = update.name(name);
@@ -83,6 +116,42 @@ impl UpdateCategoryPayload<CollectionId> for UpdateCollectionPayload {
         if let Field::Present(thumb) = self.thumb {
             update = update.thumb(thumb);
         }
-        manager.bind(id).update(update)
+        manager.bind(id).update(update)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, TS)]
+#[ts(export)]
+pub struct CreateCollectionPayload {
+    pub name: String,
+    #[serde(default)]
+    pub source: Option<String>,
+    #[serde(default)]
+    pub thumb: Option<FileMetaId>,
+}
+
+impl CreateCategoryPayload<CollectionId> for CreateCollectionPayload {
+    fn create(self, manager: &PostArchiverManager) -> ApiResult<CollectionId> {
+        let source = match self.source {
+            Some(source) => {
+                if manager.find_collection_by_source(&source)?.is_some() {
+                    return Err(already_exists("collection", "source", &source));
+                }
+                Some(source)
+            }
+            None => None,
+        };
+
+        let id = manager.import_collection(UnsyncCollection::new(
+            self.name,
+            source
+                .clone()
+                .unwrap_or_else(|| pending_source("collection")),
+        ))?;
+        manager
+            .bind(id)
+            .update(UpdateCollection::default().source(source).thumb(self.thumb))?;
+        Ok(id)
     }
 }
@@ -4,58 +4,97 @@ pub mod platform;
 pub mod post;
 pub mod tag;
 
-use std::{fmt::Debug, hash::Hash};
+use std::{collections::HashMap, fmt::Debug, hash::Hash};
 
 use axum::{
-    Json, Router,
-    extract::{Path, State},
-    http::StatusCode,
-    routing::get,
+    Router,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::Response,
+    routing::{get, post},
 };
-use axum_extra::extract::Query;
+use chrono::Utc;
 use post_archiver::{
+    AuthorId, CollectionId, FileMetaId, PlatformId, TagId,
     manager::{BindableId, PostArchiverManager},
     query::{Countable, Paginate, Totalled, post::PostQuery},
 };
+use rusqlite::{Connection, params};
 use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use ts_rs::TS;
 
 use super::{
     AppState,
+    error::{ApiError, ApiErrorCode, ApiResult},
+    extract::{Json, Path, Query},
+    history::{self, Tracked},
     post::PostShortResponse,
     relation::{RequireRelations, WithRelations},
-    utils::Pagination,
+    utils::{Pagination, SortDirection, escape_like},
+    version::{Versioned, etag_of, if_match, matches, precondition_failed, with_etag},
 };
 
-pub trait Category: RequireRelations + Serialize + Debug + TS + Sized + 'static {
+pub trait Category:
+    Tracked + RequireRelations + Serialize + Debug + TS + Sized + Send + 'static
+{
     type Id: From<u32> + BindableId + Debug + Serialize + Copy + Eq + Hash + Sync + Send + 'static;
     type UpdatePayload: UpdateCategoryPayload<Self::Id>;
+    type CreatePayload: CreateCategoryPayload<Self::Id>;
 
     const ROUTE: &'static str;
+    // link table and column counting the posts of an entity
+    const POSTS: (&'static str, &'static str);
+
+    fn raw_id(&self) -> u32;
 
     fn list_query(
         manager: &PostArchiverManager,
         pagination: &Pagination,
-        search: &str,
-    ) -> post_archiver::error::Result<Totalled<Vec<Self>>>;
+        filter: &Filter,
+    ) -> ApiResult<Totalled<Vec<Self>>>;
 
     fn get_single(
         manager: &PostArchiverManager,
         id: Self::Id,
     ) -> post_archiver::error::Result<Option<Self>>;
 
-    fn delete_entity(
+    fn delete_entity(manager: &PostArchiverManager, id: Self::Id) -> ApiResult<()>;
+
+    fn merge_entities(
         manager: &PostArchiverManager,
-        id: Self::Id,
-    ) -> post_archiver::error::Result<()>;
+        target: Self::Id,
+        sources: &[Self::Id],
+    ) -> ApiResult<()>;
 
     fn filter_posts<T>(query: PostQuery<T>, id: Self::Id) -> PostQuery<T>;
 
+    // columns of `t` usable by `list_by_usage`, count sorting is handled there
+    fn sort_column(sort: CategorySort) -> Option<&'static str> {
+        match sort {
+            CategorySort::Id => Some("t.id"),
+            CategorySort::Name => Some("t.name COLLATE NOCASE"),
+            CategorySort::Updated | CategorySort::Count => None,
+        }
+    }
+
+    fn current_version(
+        manager: &PostArchiverManager,
+        id: Self::Id,
+    ) -> ApiResult<Option<Versioned<serde_json::Value>>> {
+        let Some(item) = Self::get_single(manager, id)? else {
+            return Ok(None);
+        };
+        let etag = etag_of(&item)?;
+        let body =
+            serde_json::to_value(WithRelations::new(manager, item)?).map_err(ApiError::internal)?;
+        Ok(Some(Versioned { etag, body }))
+    }
+
     fn wrap_category_route(router: Router<AppState>) -> Router<AppState> {
-        router
+        Self::wrap_history_route(router, &format!("/{}", Self::ROUTE))
             .route(
                 &format!("/{}", Self::ROUTE),
-                get(list_category_handler::<Self>),
+                get(list_category_handler::<Self>).post(create_category_handler::<Self>),
             )
             .route(
                 &format!("/{}/{{id}}", Self::ROUTE),
@@ -67,6 +106,10 @@ pub trait Category: RequireRelations + Serialize + Debug + TS + Sized + 'static
                 &format!("/{}/{{id}}/posts", Self::ROUTE),
                 get(list_category_posts_handler::<Self>),
             )
+            .route(
+                &format!("/{}/{{id}}/merge", Self::ROUTE),
+                post(merge_category_handler::<Self>),
+            )
     }
 }
 
@@ -74,86 +117,522 @@ pub trait Category: RequireRelations + Serialize + Debug + TS + Sized + 'static
 pub struct Filter {
     #[serde(default)]
     pub search: String,
+    #[serde(default)]
+    pub sort: CategorySort,
+    #[serde(default)]
+    pub dir: SortDirection,
+    #[serde(default)]
+    pub unused: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CategorySort {
+    #[default]
+    Id,
+    Name,
+    Updated,
+    Count,
+}
+
+impl Filter {
+    pub fn unsupported_sort<T: Category>(&self) -> ApiError {
+        ApiError::bad_request(format!("{} cannot be sorted by {:?}", T::ROUTE, self.sort))
+            .with_field("sort")
+    }
 }
 
 async fn list_category_handler<T: Category>(
     Query(filter): Query<Filter>,
     Query(pagination): Query<Pagination>,
     State(state): State<AppState>,
-) -> Result<Json<WithRelations<Totalled<Vec<T>>>>, StatusCode> {
-    let manager = state.manager();
-    let result = T::list_query(&manager, &pagination, &filter.search)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+) -> ApiResult<Json<WithRelations<Totalled<Vec<Counted<T>>>>>> {
+    state
+        .read(move |manager| {
+            let result = if filter.unused {
+                list_by_usage::<T>(manager, &pagination, &filter)?
+            } else {
+                T::list_query(manager, &pagination, &filter)?
+            };
+
+            let ids: Vec<u32> = result.items.iter().map(T::raw_id).collect();
+            let mut counts = post_counts::<T>(manager.conn(), &ids)?;
+            let result = Totalled {
+                items: result
+                    .items
+                    .into_iter()
+                    .map(|item| Counted {
+                        post_count: counts.remove(&item.raw_id()).unwrap_or_default(),
+                        item,
+                    })
+                    .collect(),
+                total: result.total,
+            };
+            Ok(Json(WithRelations::new(manager, result)?))
+        })
+        .await
+}
+
+#[derive(Debug, Serialize)]
+pub struct Counted<T> {
+    #[serde(flatten)]
+    pub item: T,
+    pub post_count: u64,
+}
+
+impl<T: RequireRelations> RequireRelations for Counted<T> {
+    fn authors(&self) -> Vec<AuthorId> {
+        self.item.authors()
+    }
+    fn collections(&self) -> Vec<CollectionId> {
+        self.item.collections()
+    }
+    fn platforms(&self) -> Vec<PlatformId> {
+        self.item.platforms()
+    }
+    fn tags(&self) -> Vec<TagId> {
+        self.item.tags()
+    }
+    fn file_metas(&self) -> Vec<FileMetaId> {
+        self.item.file_metas()
+    }
+}
+
+fn post_counts<T: Category>(conn: &Connection, ids: &[u32]) -> rusqlite::Result<HashMap<u32, u64>> {
+    if ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let (table, column) = T::POSTS;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {column}, COUNT(*) FROM {table}
+        WHERE {column} IN (SELECT value FROM json_each(?1)) GROUP BY {column}"
+    ))?;
+    let ids = serde_json::to_string(ids).unwrap();
+    stmt.query_map(params![ids], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect()
+}
+
+// sorting by usage and filtering unused entities is beyond the query builders
+fn list_by_usage<T: Category>(
+    manager: &PostArchiverManager,
+    pagination: &Pagination,
+    filter: &Filter,
+) -> ApiResult<Totalled<Vec<T>>> {
+    let (table, column) = T::POSTS;
+    let usage = format!("(SELECT COUNT(*) FROM {table} WHERE {column} = t.id)");
+    let order = match filter.sort {
+        CategorySort::Count => usage.clone(),
+        sort => T::sort_column(sort)
+            .ok_or_else(|| filter.unsupported_sort::<T>())?
+            .to_string(),
+    };
 
-    WithRelations::new(&manager, result)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
-        .map(Json::from)
+    let mut conditions = vec![r"(?1 = '' OR t.name LIKE '%' || ?1 || '%' ESCAPE '\')"];
+    let unused = format!("{usage} = 0");
+    if filter.unused {
+        conditions.push(&unused);
+    }
+    let conditions = conditions.join(" AND ");
+    let search = escape_like(&filter.search);
+
+    let conn = manager.conn();
+    let total = conn.query_row(
+        &format!("SELECT COUNT(*) FROM {} t WHERE {conditions}", T::TABLE),
+        params![search],
+        |row| row.get(0),
+    )?;
+
+    let dir = filter.dir.sql();
+    let mut stmt = conn.prepare(&format!(
+        "SELECT t.id FROM {} t WHERE {conditions} ORDER BY {order} {dir}, t.id {dir} LIMIT ?2 OFFSET ?3",
+        T::TABLE
+    ))?;
+    let ids = stmt
+        .query_map(
+            params![
+                search,
+                pagination.limit(),
+                pagination.limit() * pagination.page()
+            ],
+            |row| row.get::<_, u32>(0),
+        )?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let mut items = Vec::with_capacity(ids.len());
+    for id in ids {
+        items.extend(T::get_single(manager, id.into())?);
+    }
+    Ok(Totalled { items, total })
 }
 
 async fn get_category_handler<T: Category>(
     Path(id): Path<u32>,
     State(state): State<AppState>,
-) -> Result<Json<WithRelations<T>>, StatusCode> {
-    let manager = state.manager();
-    let id: T::Id = id.into();
-
-    let item = T::get_single(&manager, id)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .ok_or(StatusCode::NOT_FOUND)?;
-
-    WithRelations::new(&manager, item)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
-        .map(Json::from)
+) -> ApiResult<Versioned<WithRelations<T>>> {
+    state
+        .read(move |manager| {
+            let item = T::get_single(manager, id.into())?
+                .ok_or_else(|| ApiError::not_found(T::ROUTE, id))?;
+            Ok(Versioned {
+                etag: etag_of(&item)?,
+                body: WithRelations::new(manager, item)?,
+            })
+        })
+        .await
 }
 
 async fn delete_category_handler<T: Category>(
     Path(id): Path<u32>,
     State(state): State<AppState>,
-) -> Result<StatusCode, StatusCode> {
-    let manager = state.manager();
-    let id: T::Id = id.into();
-
-    T::delete_entity(&manager, id)
-        .map(|_| StatusCode::NO_CONTENT)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+) -> ApiResult<StatusCode> {
+    state
+        .transaction(move |manager| {
+            T::get_single(manager, id.into())?.ok_or_else(|| ApiError::not_found(T::ROUTE, id))?;
+            history::record::<T>(manager, id, "delete")?;
+            T::delete_entity(manager, id.into())
+        })
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
 }
 
 async fn update_category_handler<T: Category>(
-    Path(id): Path<u32>,
+    Path(raw_id): Path<u32>,
     State(state): State<AppState>,
+    headers: HeaderMap,
     Json(payload): Json<T::UpdatePayload>,
-) -> Result<StatusCode, StatusCode> {
-    let manager = state.manager();
-    let id: T::Id = id.into();
+) -> ApiResult<Response> {
+    let expected = if_match(&headers);
+    state
+        .transaction(move |manager| {
+            let id: T::Id = raw_id.into();
+            if let Some(expected) = expected {
+                let current = T::current_version(manager, id)?
+                    .ok_or_else(|| ApiError::not_found(T::ROUTE, raw_id))?;
+                if !matches(&expected, &current.etag) {
+                    return Ok(precondition_failed(current));
+                }
+            }
 
-    payload
-        .apply(&manager, id)
-        .map(|_| StatusCode::NO_CONTENT)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+            history::record::<T>(manager, raw_id, "update")?;
+            payload.apply(manager, id)?;
+
+            let etag = T::current_version(manager, id)?.map(|current| current.etag);
+            Ok(with_etag(StatusCode::NO_CONTENT, etag))
+        })
+        .await
 }
 
 pub trait UpdateCategoryPayload<Id>: DeserializeOwned + Debug + Send + Sync + 'static {
-    fn apply(self, manager: &PostArchiverManager, id: Id) -> post_archiver::error::Result<()>;
+    fn apply(self, manager: &PostArchiverManager, id: Id) -> ApiResult<()>;
+}
+
+async fn create_category_handler<T: Category>(
+    State(state): State<AppState>,
+    Json(payload): Json<T::CreatePayload>,
+) -> ApiResult<(StatusCode, Json<WithRelations<T>>)> {
+    let item = state
+        .transaction(move |manager| {
+            let id = payload.create(manager)?;
+            let item = T::get_single(manager, id)?
+                .ok_or_else(|| ApiError::internal("created entity could not be read back"))?;
+            Ok(WithRelations::new(manager, item)?)
+        })
+        .await?;
+
+    Ok((StatusCode::CREATED, Json(item)))
+}
+
+pub trait CreateCategoryPayload<Id>: DeserializeOwned + Debug + Send + Sync + 'static {
+    fn create(self, manager: &PostArchiverManager) -> ApiResult<Id>;
+}
+
+// the importers return an existing match instead of failing, creating must not
+pub fn already_exists(kind: &str, field: &str, value: &str) -> ApiError {
+    ApiError::new(
+        ApiErrorCode::Conflict,
+        format!("{kind} with {field} {value:?} already exists"),
+    )
+    .with_field(field)
+}
+
+// the importers key posts and collections on their source, entities created
+// without one go in under a unique placeholder that is cleared right after
+pub fn pending_source(kind: &str) -> String {
+    format!(
+        "post-archiver-editor:pending-{kind}:{}",
+        Utc::now().timestamp_nanos_opt().unwrap_or_default()
+    )
+}
+
+#[derive(Debug, Deserialize, Serialize, TS)]
+#[ts(export)]
+pub struct MergeCategoryPayload {
+    pub sources: Vec<u32>,
+}
+
+async fn merge_category_handler<T: Category>(
+    Path(id): Path<u32>,
+    State(state): State<AppState>,
+    Json(mut payload): Json<MergeCategoryPayload>,
+) -> ApiResult<StatusCode> {
+    let target: T::Id = id.into();
+
+    payload.sources.sort_unstable();
+    payload.sources.dedup();
+    payload.sources.retain(|&source| source != id);
+    let sources: Vec<T::Id> = payload.sources.iter().copied().map(T::Id::from).collect();
+    if sources.is_empty() {
+        return Err(ApiError::bad_request("no other entity to merge").with_field("sources"));
+    }
+
+    state
+        .transaction(move |manager| {
+            T::get_single(manager, target)?.ok_or_else(|| ApiError::not_found(T::ROUTE, id))?;
+            for &source in &payload.sources {
+                T::get_single(manager, source.into())?
+                    .ok_or_else(|| ApiError::not_found(T::ROUTE, source).with_field("sources"))?;
+            }
+
+            history::record::<T>(manager, id, "merge")?;
+            for &source in &payload.sources {
+                history::record::<T>(manager, source, "merge")?;
+            }
+
+            T::merge_entities(manager, target, &sources)
+        })
+        .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// re-point links from `source` to `target`, dropping rows `target` already has
+fn merge_links(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    target: u32,
+    source: u32,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        &format!("UPDATE OR IGNORE {table} SET {column} = ?1 WHERE {column} = ?2"),
+        params![target, source],
+    )?;
+    conn.execute(
+        &format!("DELETE FROM {table} WHERE {column} = ?1"),
+        params![source],
+    )?;
+    Ok(())
+}
+
+fn merge_thumb(conn: &Connection, table: &str, target: u32, source: u32) -> rusqlite::Result<()> {
+    conn.execute(
+        &format!(
+            "UPDATE {table} SET thumb = (SELECT thumb FROM {table} WHERE id = ?2) WHERE id = ?1 AND thumb IS NULL"
+        ),
+        params![target, source],
+    )?;
+    Ok(())
 }
 
 async fn list_category_posts_handler<T: Category>(
     Path(id): Path<u32>,
     Query(pagination): Query<Pagination>,
     State(state): State<AppState>,
-) -> Result<Json<WithRelations<Totalled<Vec<PostShortResponse>>>>, StatusCode> {
-    let manager = state.manager();
-    let id: T::Id = id.into();
-
-    let query = T::filter_posts(manager.posts(), id);
-
-    use post_archiver::query::Query;
-    let result = query
-        .pagination(pagination.limit(), pagination.page())
-        .with_total()
-        .query::<PostShortResponse>()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    WithRelations::new(&manager, result)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
-        .map(Json::from)
+) -> ApiResult<Json<WithRelations<Totalled<Vec<PostShortResponse>>>>> {
+    state
+        .read(move |manager| {
+            let query = T::filter_posts(manager.posts(), id.into());
+
+            use post_archiver::query::Query;
+            let result = query
+                .pagination(pagination.limit(), pagination.page())
+                .with_total()
+                .query::<PostShortResponse>()?;
+
+            Ok(Json(WithRelations::new(manager, result)?))
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use post_archiver::{
+        Author, AuthorId, Platform, PlatformId, Post, PostId, Tag, TagId,
+        importer::{UnsyncAlias, UnsyncAuthor, UnsyncPost, UnsyncTag},
+    };
+    use serde_json::{Value, json};
+
+    use super::{Category, CreateCategoryPayload};
+    use crate::api::{
+        category::{
+            author::CreateAuthorPayload, collection::CreateCollectionPayload,
+            platform::CreatePlatformPayload, post::CreatePostPayload, tag::CreateTagPayload,
+        },
+        error::ApiErrorCode,
+        testing::TestArchive,
+    };
+
+    fn create<T: CreateCategoryPayload<Id>, Id>(archive: &TestArchive, payload: Value) -> Id {
+        serde_json::from_value::<T>(payload)
+            .unwrap()
+            .create(archive)
+            .unwrap()
+    }
+
+    fn conflict<T: CreateCategoryPayload<Id>, Id>(archive: &TestArchive, payload: Value) {
+        let err = serde_json::from_value::<T>(payload)
+            .unwrap()
+            .create(archive)
+            .err()
+            .expect("duplicate was created");
+        assert_eq!(err.code, ApiErrorCode::Conflict);
+    }
+
+    #[test]
+    fn creates_platforms_and_tags_once() {
+        let archive = TestArchive::new();
+        let platform: PlatformId =
+            create::<CreatePlatformPayload, _>(&archive, json!({ "name": "example" }));
+        conflict::<CreatePlatformPayload, _>(&archive, json!({ "name": "example" }));
+
+        let tag: TagId =
+            create::<CreateTagPayload, _>(&archive, json!({ "name": "art", "platform": platform }));
+        assert_eq!(
+            archive.get_tag(tag).unwrap().unwrap().platform,
+            Some(platform)
+        );
+        // tag names are unique across platforms
+        conflict::<CreateTagPayload, _>(&archive, json!({ "name": "art" }));
+    }
+
+    #[test]
+    fn creates_collections_with_and_without_source() {
+        let archive = TestArchive::new();
+        let first = create::<CreateCollectionPayload, _>(&archive, json!({ "name": "a" }));
+        let second = create::<CreateCollectionPayload, _>(&archive, json!({ "name": "a" }));
+        assert_ne!(first, second);
+        assert_eq!(archive.get_collection(first).unwrap().unwrap().source, None);
+
+        create::<CreateCollectionPayload, _>(&archive, json!({ "name": "b", "source": "s" }));
+        conflict::<CreateCollectionPayload, _>(&archive, json!({ "name": "c", "source": "s" }));
+    }
+
+    #[test]
+    fn creates_authors_with_unclaimed_aliases() {
+        let archive = TestArchive::new();
+        let alias = json!({ "source": "jack", "platform": 0, "link": null, "target": 0 });
+        let author: AuthorId = create::<CreateAuthorPayload, _>(
+            &archive,
+            json!({ "name": "Jack", "aliases": [alias] }),
+        );
+        assert_eq!(
+            archive.find_author_by_alias("jack", PlatformId(0)).unwrap(),
+            Some(author)
+        );
+
+        conflict::<CreateAuthorPayload, _>(&archive, json!({ "name": "Jill", "aliases": [alias] }));
+    }
+
+    #[test]
+    fn creates_posts_without_source_or_platform() {
+        let archive = TestArchive::new();
+        let post: PostId = create::<CreatePostPayload, _>(
+            &archive,
+            json!({ "title": "first", "content": ["hello"] }),
+        );
+        let post = archive.get_post(post).unwrap().unwrap();
+        assert_eq!((post.source, post.platform), (None, None));
+        assert_eq!(post.content.len(), 1);
+
+        create::<CreatePostPayload, _>(&archive, json!({ "title": "a", "source": "s" }));
+        conflict::<CreatePostPayload, _>(&archive, json!({ "title": "b", "source": "s" }));
+    }
+
+    fn post(archive: &TestArchive, source: &str, platform: PlatformId) -> PostId {
+        let post = UnsyncPost::<()>::new(platform, source.to_string(), source.to_string(), vec![]);
+        archive.import_post(post, false).unwrap().0
+    }
+
+    fn tag(archive: &TestArchive, name: &str, platform: Option<PlatformId>) -> TagId {
+        let tag = UnsyncTag {
+            name: name.to_string(),
+            platform,
+        };
+        archive.import_tag(tag).unwrap()
+    }
+
+    #[test]
+    fn merging_tags_keeps_one_link_per_post() {
+        let archive = TestArchive::new();
+        let (target, source) = (tag(&archive, "a", None), tag(&archive, "b", None));
+        let both = post(&archive, "both", PlatformId(0));
+        let only_source = post(&archive, "source", PlatformId(0));
+        archive.bind(both).add_tags(&[target, source]).unwrap();
+        archive.bind(only_source).add_tags(&[source]).unwrap();
+
+        Tag::merge_entities(&archive, target, &[source]).unwrap();
+
+        assert_eq!(archive.bind(both).list_tags().unwrap(), vec![target]);
+        assert_eq!(archive.bind(only_source).list_tags().unwrap(), vec![target]);
+        assert!(archive.get_tag(source).unwrap().is_none());
+    }
+
+    #[test]
+    fn merging_authors_moves_posts_and_aliases() {
+        let archive = TestArchive::new();
+        let author = |name: &str| {
+            let alias = UnsyncAlias::new(PlatformId(0), name.to_string());
+            let author = UnsyncAuthor::new(name.to_string()).aliases(vec![alias]);
+            archive.import_author(author).unwrap()
+        };
+        let (target, source) = (author("target"), author("source"));
+        let post = post(&archive, "post", PlatformId(0));
+        archive.bind(post).add_authors(&[source]).unwrap();
+
+        Author::merge_entities(&archive, target, &[source]).unwrap();
+
+        assert_eq!(archive.bind(post).list_authors().unwrap(), vec![target]);
+        assert_eq!(
+            archive
+                .find_author_by_alias("source", PlatformId(0))
+                .unwrap(),
+            Some(target)
+        );
+        assert!(archive.get_author(source).unwrap().is_none());
+    }
+
+    #[test]
+    fn merging_platforms_moves_posts_and_tags() {
+        let archive = TestArchive::new();
+        let target = archive.import_platform("target".to_string()).unwrap();
+        let source = archive.import_platform("source".to_string()).unwrap();
+        let post = post(&archive, "post", source);
+        let tag = tag(&archive, "art", Some(source));
+
+        Platform::merge_entities(&archive, target, &[source]).unwrap();
+
+        assert_eq!(
+            archive.get_post(post).unwrap().unwrap().platform,
+            Some(target)
+        );
+        assert_eq!(
+            archive.get_tag(tag).unwrap().unwrap().platform,
+            Some(target)
+        );
+        assert!(archive.get_platform(source).unwrap().is_none());
+    }
+
+    #[test]
+    fn unknown_platform_and_posts_cannot_be_merged() {
+        let archive = TestArchive::new();
+        let target = archive.import_platform("target".to_string()).unwrap();
+        let err = Platform::merge_entities(&archive, target, &[PlatformId(0)]).unwrap_err();
+        assert_eq!(err.code, ApiErrorCode::Constraint);
+        assert!(archive.get_platform(PlatformId(0)).unwrap().is_some());
+
+        let (a, b) = (post(&archive, "a", target), post(&archive, "b", target));
+        let err = Post::merge_entities(&archive, a, &[b]).unwrap_err();
+        assert_eq!(err.code, ApiErrorCode::Constraint);
+    }
 }
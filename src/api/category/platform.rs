@@ -1,36 +1,67 @@
 use post_archiver::{
     Platform, PlatformId,
     manager::{PostArchiverManager, UpdatePlatform},
-    query::{Countable, Paginate, Query, SortDir, Sortable, Totalled, platform::PlatformSort},
+    query::{Countable, Paginate, Query, Sortable, Totalled, platform::PlatformSort},
 };
+use rusqlite::params;
 use serde::{Deserialize, Serialize};
 use ts_rs::TS;
 
-use crate::api::{relation::RequireRelations, utils::Pagination};
+use crate::api::{
+    error::{ApiError, ApiErrorCode, ApiResult},
+    history::Tracked,
+    relation::RequireRelations,
+    utils::Pagination,
+};
 
-use super::{Category, UpdateCategoryPayload};
+use super::{
+    Category, CategorySort, CreateCategoryPayload, Filter, UpdateCategoryPayload, already_exists,
+    list_by_usage,
+};
 
 impl RequireRelations for Platform {}
 
+impl Tracked for Platform {
+    const KIND: &'static str = "platforms";
+    const TABLE: &'static str = "platforms";
+    // tags are deleted together with their platform, so they are kept whole
+    const LINKS: &'static [(&'static str, &'static str)] =
+        &[("author_aliases", "platform"), ("tags", "platform")];
+    const REFS: &'static [(&'static str, &'static str)] = &[("posts", "platform")];
+}
+
 impl Category for Platform {
     type Id = PlatformId;
     type UpdatePayload = UpdatePlatformPayload;
+    type CreatePayload = CreatePlatformPayload;
 
     const ROUTE: &'static str = "platforms";
+    const POSTS: (&'static str, &'static str) = ("posts", "platform");
+
+    fn raw_id(&self) -> u32 {
+        self.id.0
+    }
 
     fn list_query(
         manager: &PostArchiverManager,
         pagination: &Pagination,
-        search: &str,
-    ) -> post_archiver::error::Result<Totalled<Vec<Self>>> {
+        filter: &Filter,
+    ) -> ApiResult<Totalled<Vec<Self>>> {
+        let sort = match filter.sort {
+            CategorySort::Id => PlatformSort::Id,
+            CategorySort::Name => PlatformSort::Name,
+            CategorySort::Updated => return Err(filter.unsupported_sort::<Self>()),
+            CategorySort::Count => return list_by_usage::<Self>(manager, pagination, filter),
+        };
+
         let mut q = manager.platforms();
-        if !search.is_empty() {
-            q.name.contains(search);
+        if !filter.search.is_empty() {
+            q.name.contains(&filter.search);
         }
-        q.sort(PlatformSort::Id, SortDir::Desc)
+        Ok(q.sort(sort, filter.dir.into())
             .pagination(pagination.limit(), pagination.page())
             .with_total()
-            .query::<Platform>()
+            .query::<Platform>()?)
     }
 
     fn get_single(
@@ -40,11 +71,51 @@ impl Category for Platform {
         manager.get_platform(id)
     }
 
-    fn delete_entity(
+    fn delete_entity(manager: &PostArchiverManager, id: Self::Id) -> ApiResult<()> {
+        Ok(manager.bind(id).delete()?)
+    }
+
+    fn merge_entities(
         manager: &PostArchiverManager,
-        id: Self::Id,
-    ) -> post_archiver::error::Result<()> {
-        manager.bind(id).delete()
+        target: Self::Id,
+        sources: &[Self::Id],
+    ) -> ApiResult<()> {
+        // the unknown platform is where everything falls back to, it cannot go away
+        if sources.contains(&PlatformId(0)) {
+            return Err(ApiError::new(
+                ApiErrorCode::Constraint,
+                "the unknown platform cannot be merged into another",
+            )
+            .with_field("sources"));
+        }
+
+        let conn = manager.conn();
+        for &source in sources {
+            let (target, source) = (target.0, source.0);
+            conn.execute(
+                "UPDATE posts SET platform = ?1 WHERE platform = ?2",
+                params![target, source],
+            )?;
+
+            // aliases are keyed by (source, platform), keep the target's on conflict
+            conn.execute(
+                "UPDATE OR IGNORE author_aliases SET platform = ?1 WHERE platform = ?2",
+                params![target, source],
+            )?;
+            conn.execute(
+                "DELETE FROM author_aliases WHERE platform = ?1",
+                params![source],
+            )?;
+
+            // tag names are unique across platforms, so the tags move as they are
+            conn.execute(
+                "UPDATE tags SET platform = ?1 WHERE platform = ?2",
+                params![target, source],
+            )?;
+
+            manager.bind(PlatformId(source)).delete()?;
+        }
+        Ok(())
     }
 
     fn filter_posts<T>(
@@ -63,16 +134,28 @@ pub struct UpdatePlatformPayload {
 }
 
 impl UpdateCategoryPayload<PlatformId> for UpdatePlatformPayload {
-    fn apply(
-        self,
-        manager: &PostArchiverManager,
-        id: PlatformId,
-    ) -> post_archiver::error::Result<()> {
+    fn apply(self, manager: &PostArchiverManager, id: PlatformId) -> ApiResult<()> {
         let update = if let Some(name) = self.name {
             UpdatePlatform::default().name(name)
         } else {
             UpdatePlatform::default()
         };
-        manager.bind(id).update(update)
+        manager.bind(id).update(update)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, TS)]
+#[ts(export)]
+pub struct CreatePlatformPayload {
+    pub name: String,
+}
+
+impl CreateCategoryPayload<PlatformId> for CreatePlatformPayload {
+    fn create(self, manager: &PostArchiverManager) -> ApiResult<PlatformId> {
+        if manager.find_platform(&self.name)?.is_some() {
+            return Err(already_exists("platform", "name", &self.name));
+        }
+        Ok(manager.import_platform(self.name)?)
     }
 }
@@ -2,6 +2,7 @@ use axum::{Router, routing::get};
 use chrono::{DateTime, Utc};
 use post_archiver::{
     AuthorId, CollectionId, Comment, Content, FileMetaId, PlatformId, Post, PostId, TagId,
+    importer::UnsyncPost,
     manager::{PostArchiverManager, UpdatePost},
     query::Totalled,
 };
@@ -11,13 +12,19 @@ use ts_rs::TS;
 
 use crate::api::{
     AppState,
-    category::{delete_category_handler, update_category_handler},
-    post::{get_post_handler, list_post_handler},
-    relation::RequireRelations,
+    category::{create_category_handler, update_category_handler},
+    error::{ApiError, ApiErrorCode, ApiResult},
+    history::Tracked,
+    post::{PostResponse, delete_post_handler, get_post_handler, list_post_handler, trash_path},
+    relation::{RequireRelations, WithRelations},
+    search,
     utils::Pagination,
+    version::{Versioned, etag_of},
 };
 
-use super::{Category, UpdateCategoryPayload};
+use super::{
+    Category, CreateCategoryPayload, Filter, UpdateCategoryPayload, already_exists, pending_source,
+};
 
 impl RequireRelations for Post {
     fn platforms(&self) -> Vec<PlatformId> {
@@ -28,17 +35,53 @@ impl RequireRelations for Post {
     }
 }
 
+impl Tracked for Post {
+    const KIND: &'static str = "posts";
+    const TABLE: &'static str = "posts";
+    const LINKS: &'static [(&'static str, &'static str)] = &[
+        ("author_posts", "post"),
+        ("post_tags", "post"),
+        ("collection_posts", "post"),
+        ("file_metas", "post"),
+    ];
+
+    fn restored(manager: &PostArchiverManager, entity: u32, revision: i64) -> ApiResult<()> {
+        // files trashed by a cascading delete go back next to their restored metas
+        for file in manager.bind(PostId(entity)).list_file_metas()? {
+            let trash = trash_path(&manager.path, revision, file);
+            if !trash.exists() {
+                continue;
+            }
+            let Some(file_meta) = manager.get_file_meta(file)? else {
+                continue;
+            };
+            let path = manager.path.join(file_meta.path());
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::rename(trash, path)?;
+        }
+        search::index_post(manager, PostId(entity))
+    }
+}
+
 impl Category for Post {
     type Id = PostId;
     type UpdatePayload = UpdatePostPayload;
+    type CreatePayload = CreatePostPayload;
 
     const ROUTE: &'static str = "posts";
+    const POSTS: (&'static str, &'static str) = ("posts", "id");
+
+    fn raw_id(&self) -> u32 {
+        self.id.0
+    }
 
     fn list_query(
         _manager: &PostArchiverManager,
         _pagination: &Pagination,
-        _search: &str,
-    ) -> post_archiver::error::Result<Totalled<Vec<Self>>> {
+        _filter: &Filter,
+    ) -> ApiResult<Totalled<Vec<Self>>> {
         unimplemented!("replaced by custom handler to support more query parameters")
     }
 
@@ -49,20 +92,33 @@ impl Category for Post {
         manager.get_post(id)
     }
 
-    fn delete_entity(
-        manager: &PostArchiverManager,
-        id: Self::Id,
-    ) -> post_archiver::error::Result<()> {
-        manager.bind(id).delete()
+    fn delete_entity(manager: &PostArchiverManager, id: Self::Id) -> ApiResult<()> {
+        manager.bind(id).delete()?;
+        search::remove_post(manager.conn(), id)?;
+        Ok(())
+    }
+
+    fn merge_entities(
+        _manager: &PostArchiverManager,
+        _target: Self::Id,
+        _sources: &[Self::Id],
+    ) -> ApiResult<()> {
+        Err(ApiError::new(
+            ApiErrorCode::Constraint,
+            "posts cannot be merged",
+        ))
     }
 
     fn wrap_category_route(router: Router<AppState>) -> Router<AppState> {
-        router
-            .route(&format!("/{}", Self::ROUTE), get(list_post_handler))
+        Self::wrap_history_route(router, &format!("/{}", Self::ROUTE))
+            .route(
+                &format!("/{}", Self::ROUTE),
+                get(list_post_handler).post(create_category_handler::<Self>),
+            )
             .route(
                 &format!("/{}/{{id}}", Self::ROUTE),
                 get(get_post_handler)
-                    .delete(delete_category_handler::<Self>)
+                    .delete(delete_post_handler)
                     .patch(update_category_handler::<Self>),
             )
     }
@@ -73,6 +129,19 @@ impl Category for Post {
     ) -> post_archiver::query::post::PostQuery<T> {
         unimplemented!("Posts cannot be filtered by posts")
     }
+
+    fn current_version(
+        manager: &PostArchiverManager,
+        id: Self::Id,
+    ) -> ApiResult<Option<Versioned<serde_json::Value>>> {
+        let Some(post) = PostResponse::load(manager, id)? else {
+            return Ok(None);
+        };
+        let etag = etag_of(&post)?;
+        let body =
+            serde_json::to_value(WithRelations::new(manager, post)?).map_err(ApiError::internal)?;
+        Ok(Some(Versioned { etag, body }))
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, TS)]
@@ -96,7 +165,7 @@ pub struct UpdatePostPayload {
 }
 
 impl UpdateCategoryPayload<PostId> for UpdatePostPayload {
-    fn apply(self, manager: &PostArchiverManager, id: PostId) -> post_archiver::error::Result<()> {
+    fn apply(self, manager: &PostArchiverManager, id: PostId) -> ApiResult<()> {
         let mut update = UpdatePost::default();
         if let Some(title) = self.title {
             update = update.title(title);
@@ -186,6 +255,73 @@ impl UpdateCategoryPayload<PostId> for UpdatePostPayload {
             bound.add_collections(&to_add)?;
         }
 
-        Ok(())
+        search::index_post(manager, id)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, TS)]
+#[ts(export)]
+pub struct CreatePostPayload {
+    pub title: String,
+    #[serde(default)]
+    pub source: Option<String>,
+    #[serde(default)]
+    pub platform: Option<PlatformId>,
+    #[serde(default)]
+    pub content: Vec<Content>,
+    #[serde(default)]
+    pub comments: Vec<Comment>,
+    #[serde(default)]
+    pub published: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub updated: Option<DateTime<Utc>>,
+    // Relations
+    #[serde(default)]
+    pub authors: Vec<AuthorId>,
+    #[serde(default)]
+    pub collections: Vec<CollectionId>,
+    #[serde(default)]
+    pub tags: Vec<TagId>,
+}
+
+impl CreateCategoryPayload<PostId> for CreatePostPayload {
+    fn create(self, manager: &PostArchiverManager) -> ApiResult<PostId> {
+        if let Some(source) = &self.source
+            && manager.find_post(source)?.is_some()
+        {
+            return Err(already_exists("post", "source", source));
+        }
+
+        let mut post = UnsyncPost::<()>::new(
+            self.platform.unwrap_or(PlatformId(0)),
+            self.source
+                .clone()
+                .unwrap_or_else(|| pending_source("post")),
+            self.title,
+            vec![],
+        )
+        .comments(self.comments)
+        .authors(self.authors);
+        if let Some(published) = self.published {
+            post = post.published(published);
+        }
+        if let Some(updated) = self.updated {
+            post = post.updated(updated);
+        }
+        let (id, ..) = manager.import_post(post, false)?;
+
+        // content references existing file metas, which the importer cannot take
+        let bound = manager.bind(id);
+        bound.update(
+            UpdatePost::default()
+                .content(self.content)
+                .source(self.source)
+                .platform(self.platform),
+        )?;
+        bound.add_tags(&self.tags)?;
+        bound.add_collections(&self.collections)?;
+
+        search::index_post(manager, id)?;
+        Ok(id)
     }
 }
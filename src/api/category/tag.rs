@@ -1,14 +1,22 @@
 use optional_field::{Field, serde_optional_fields};
 use post_archiver::{
     PlatformId, Tag, TagId,
+    importer::UnsyncTag,
     manager::{PostArchiverManager, UpdateTag},
-    query::{Countable, Paginate, Query, SortDir, Sortable, Totalled, tag::TagSort},
+    query::{Countable, Paginate, Query, Sortable, Totalled, tag::TagSort},
 };
+use rusqlite::{OptionalExtension, params};
 use serde::{Deserialize, Serialize};
+use ts_rs::TS;
 
-use crate::api::{relation::RequireRelations, utils::Pagination};
+use crate::api::{
+    error::ApiResult, history::Tracked, relation::RequireRelations, utils::Pagination,
+};
 
-use super::{Category, UpdateCategoryPayload};
+use super::{
+    Category, CategorySort, CreateCategoryPayload, Filter, UpdateCategoryPayload, already_exists,
+    list_by_usage, merge_links,
+};
 
 impl RequireRelations for Tag {
     fn platforms(&self) -> Vec<PlatformId> {
@@ -16,25 +24,44 @@ impl RequireRelations for Tag {
     }
 }
 
+impl Tracked for Tag {
+    const KIND: &'static str = "tags";
+    const TABLE: &'static str = "tags";
+    const LINKS: &'static [(&'static str, &'static str)] = &[("post_tags", "tag")];
+}
+
 impl Category for Tag {
     type Id = TagId;
     type UpdatePayload = UpdateTagPayload;
+    type CreatePayload = CreateTagPayload;
 
     const ROUTE: &'static str = "tags";
+    const POSTS: (&'static str, &'static str) = ("post_tags", "tag");
+
+    fn raw_id(&self) -> u32 {
+        self.id.0
+    }
 
     fn list_query(
         manager: &PostArchiverManager,
         pagination: &Pagination,
-        search: &str,
-    ) -> post_archiver::error::Result<Totalled<Vec<Self>>> {
+        filter: &Filter,
+    ) -> ApiResult<Totalled<Vec<Self>>> {
+        let sort = match filter.sort {
+            CategorySort::Id => TagSort::Id,
+            CategorySort::Name => TagSort::Name,
+            CategorySort::Updated => return Err(filter.unsupported_sort::<Self>()),
+            CategorySort::Count => return list_by_usage::<Self>(manager, pagination, filter),
+        };
+
         let mut q = manager.tags();
-        if !search.is_empty() {
-            q.name.contains(search);
+        if !filter.search.is_empty() {
+            q.name.contains(&filter.search);
         }
-        q.sort(TagSort::Id, SortDir::Desc)
+        Ok(q.sort(sort, filter.dir.into())
             .pagination(pagination.limit(), pagination.page())
             .with_total()
-            .query::<Tag>()
+            .query::<Tag>()?)
     }
 
     fn get_single(
@@ -44,11 +71,20 @@ impl Category for Tag {
         manager.get_tag(id)
     }
 
-    fn delete_entity(
+    fn delete_entity(manager: &PostArchiverManager, id: Self::Id) -> ApiResult<()> {
+        Ok(manager.bind(id).delete()?)
+    }
+
+    fn merge_entities(
         manager: &PostArchiverManager,
-        id: Self::Id,
-    ) -> post_archiver::error::Result<()> {
-        manager.bind(id).delete()
+        target: Self::Id,
+        sources: &[Self::Id],
+    ) -> ApiResult<()> {
+        for &source in sources {
+            merge_links(manager.conn(), "post_tags", "tag", target.0, source.0)?;
+            manager.bind(source).delete()?;
+        }
+        Ok(())
     }
 
     fn filter_posts<T>(
@@ -68,7 +104,7 @@ pub struct UpdateTagPayload {
 }
 
 impl UpdateCategoryPayload<TagId> for UpdateTagPayload {
-    fn apply(self, manager: &PostArchiverManager, id: TagId) -> post_archiver::error::Result<()> {
+    fn apply(self, manager: &PostArchiverManager, id: TagId) -> ApiResult<()> {
         let mut update = UpdateTag::default();
         if let Some(name) = self.name {
             update = update.name(name);
@@ -76,6 +112,36 @@ impl UpdateCategoryPayload<TagId> for UpdateTagPayload {
         if let Field::Present(platform) = self.platform {
             update = update.platform(platform);
         }
-        manager.bind(id).update(update)
+        manager.bind(id).update(update)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, TS)]
+#[ts(export)]
+pub struct CreateTagPayload {
+    pub name: String,
+    #[serde(default)]
+    pub platform: Option<PlatformId>,
+}
+
+impl CreateCategoryPayload<TagId> for CreateTagPayload {
+    fn create(self, manager: &PostArchiverManager) -> ApiResult<TagId> {
+        // tag names are unique across platforms
+        let existing: Option<u32> = manager
+            .conn()
+            .query_row(
+                "SELECT id FROM tags WHERE name = ?1",
+                params![self.name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if existing.is_some() {
+            return Err(already_exists("tag", "name", &self.name));
+        }
+        Ok(manager.import_tag(UnsyncTag {
+            name: self.name,
+            platform: self.platform,
+        })?)
     }
 }
@@ -0,0 +1,216 @@
+use std::{error::Error as StdError, fmt::Display, io};
+
+use axum::{
+    Json,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use rusqlite::ErrorCode;
+use serde::Serialize;
+use tracing::{debug, error};
+use ts_rs::TS;
+
+pub type ApiResult<T> = Result<T, ApiError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, TS)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum ApiErrorCode {
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    Conflict,
+    Constraint,
+    ReadOnly,
+    TooManyRequests,
+    Io,
+    Database,
+    Internal,
+}
+
+impl ApiErrorCode {
+    pub fn status(self) -> StatusCode {
+        match self {
+            Self::BadRequest => StatusCode::BAD_REQUEST,
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::Forbidden | Self::ReadOnly => StatusCode::FORBIDDEN,
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::Conflict => StatusCode::CONFLICT,
+            Self::Constraint => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+            Self::Io | Self::Database | Self::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export)]
+pub struct ApiError {
+    pub code: ApiErrorCode,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<u32>,
+}
+
+impl ApiError {
+    pub fn new(code: ApiErrorCode, message: impl Display) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+            field: None,
+            id: None,
+        }
+    }
+
+    pub fn not_found(kind: &str, id: u32) -> Self {
+        Self::new(ApiErrorCode::NotFound, format!("{kind} {id} not found")).with_id(id)
+    }
+
+    pub fn bad_request(message: impl Display) -> Self {
+        Self::new(ApiErrorCode::BadRequest, message)
+    }
+
+    pub fn internal(message: impl Display) -> Self {
+        Self::new(ApiErrorCode::Internal, message)
+    }
+
+    pub fn with_field(mut self, field: impl Into<String>) -> Self {
+        self.field = Some(field.into());
+        self
+    }
+
+    pub fn with_id(mut self, id: u32) -> Self {
+        self.id = Some(id);
+        self
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.code.status()
+    }
+
+    fn from_source(err: &(dyn StdError + 'static)) -> Self {
+        let mut source = Some(err);
+        while let Some(err) = source {
+            if let Some(err) = err.downcast_ref::<rusqlite::Error>() {
+                return Self::from_sqlite(err);
+            }
+            if let Some(err) = err.downcast_ref::<io::Error>() {
+                return Self::from_io(err);
+            }
+            source = err.source();
+        }
+        Self::internal(err)
+    }
+
+    fn from_sqlite(err: &rusqlite::Error) -> Self {
+        let code = match err {
+            rusqlite::Error::QueryReturnedNoRows => ApiErrorCode::NotFound,
+            rusqlite::Error::SqliteFailure(failure, _) => match failure.code {
+                ErrorCode::ConstraintViolation => {
+                    // SQLITE_CONSTRAINT_UNIQUE and SQLITE_CONSTRAINT_PRIMARYKEY
+                    if matches!(failure.extended_code, 2067 | 1555) {
+                        ApiErrorCode::Conflict
+                    } else {
+                        ApiErrorCode::Constraint
+                    }
+                }
+                ErrorCode::ReadOnly => ApiErrorCode::ReadOnly,
+                ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked => ApiErrorCode::Conflict,
+                _ => ApiErrorCode::Database,
+            },
+            _ => ApiErrorCode::Database,
+        };
+        // the driver's message names tables and constraints, it stays in the log
+        let message = match code {
+            ApiErrorCode::NotFound => "the requested row does not exist",
+            ApiErrorCode::Conflict => "the change conflicts with existing data",
+            ApiErrorCode::Constraint => "the change breaks a reference or constraint",
+            ApiErrorCode::ReadOnly => "the archive is read-only",
+            _ => "the database could not complete the request",
+        };
+        if code.status().is_server_error() {
+            error!("database error: {err}");
+        } else {
+            debug!("database error: {err}");
+        }
+        Self::new(code, message)
+    }
+
+    fn from_io(err: &io::Error) -> Self {
+        let code = match err.kind() {
+            io::ErrorKind::NotFound => ApiErrorCode::NotFound,
+            io::ErrorKind::PermissionDenied => ApiErrorCode::Forbidden,
+            io::ErrorKind::AlreadyExists => ApiErrorCode::Conflict,
+            io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData => ApiErrorCode::BadRequest,
+            _ => ApiErrorCode::Io,
+        };
+        Self::new(code, err)
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+impl From<ApiErrorCode> for ApiError {
+    fn from(code: ApiErrorCode) -> Self {
+        let message = code.status().canonical_reason().unwrap_or("Unknown error");
+        Self::new(code, message)
+    }
+}
+
+impl From<post_archiver::error::Error> for ApiError {
+    fn from(err: post_archiver::error::Error) -> Self {
+        Self::from_source(&err)
+    }
+}
+
+impl From<rusqlite::Error> for ApiError {
+    fn from(err: rusqlite::Error) -> Self {
+        Self::from_sqlite(&err)
+    }
+}
+
+impl From<io::Error> for ApiError {
+    fn from(err: io::Error) -> Self {
+        Self::from_io(&err)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        if status.is_server_error() {
+            error!("{self}");
+        }
+        (status, Json(self)).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    use super::{ApiError, ApiErrorCode};
+
+    #[test]
+    fn database_errors_hide_the_driver_message() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE secret_table (name TEXT UNIQUE); INSERT INTO secret_table VALUES ('a');",
+        )
+        .unwrap();
+        let err = conn
+            .execute("INSERT INTO secret_table VALUES ('a')", [])
+            .unwrap_err();
+
+        let err = ApiError::from(err);
+        assert_eq!(err.code, ApiErrorCode::Conflict);
+        assert!(!err.message.contains("secret_table"), "{}", err.message);
+    }
+}
@@ -0,0 +1,300 @@
+use std::{
+    collections::HashSet,
+    fs,
+    io::{self, Write},
+    mem,
+    path::{Path as FsPath, PathBuf},
+};
+
+use axum::{
+    Router,
+    body::{Body, Bytes},
+    extract::State,
+    http::header,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use chrono::{DateTime, Utc};
+use post_archiver::{
+    Alias, Author, AuthorId, Collection, CollectionId, FileMetaId, Platform, PlatformId, Post,
+    PostId, Tag, TagId, manager::PostArchiverManager, query::Query as _,
+};
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tracing::{error, warn};
+
+use super::{
+    AppState,
+    category::Category,
+    error::{ApiError, ApiResult},
+    extract::{Path, Query},
+    filter::PostFilter,
+    post::{PostResponse, PostShortResponse},
+    relation::{RequireRelations, WithRelations},
+    tar::TarWriter,
+};
+
+pub const BUNDLE_VERSION: u32 = 1;
+pub const MANIFEST: &str = "manifest.json";
+
+pub fn wrap_export_route(router: Router<AppState>) -> Router<AppState> {
+    router
+        .route("/posts/export", get(export_filter_handler))
+        .route("/posts/{id}/export", get(export_post_handler))
+        .route(
+            &format!("/{}/{{id}}/export", Author::ROUTE),
+            get(export_category_handler::<Author>),
+        )
+        .route(
+            &format!("/{}/{{id}}/export", Tag::ROUTE),
+            get(export_category_handler::<Tag>),
+        )
+        .route(
+            &format!("/{}/{{id}}/export", Collection::ROUTE),
+            get(export_category_handler::<Collection>),
+        )
+        .route(
+            &format!("/{}/{{id}}/export", Platform::ROUTE),
+            get(export_category_handler::<Platform>),
+        )
+}
+
+#[derive(Debug, Serialize)]
+pub struct BundleManifest {
+    pub version: u32,
+    pub exported: DateTime<Utc>,
+    pub posts: Vec<BundlePost>,
+    pub aliases: Vec<Alias>,
+}
+
+impl RequireRelations for BundleManifest {
+    fn authors(&self) -> Vec<AuthorId> {
+        self.posts.authors()
+    }
+    fn collections(&self) -> Vec<CollectionId> {
+        self.posts.collections()
+    }
+    // aliases may sit on platforms none of the exported posts use
+    fn platforms(&self) -> Vec<PlatformId> {
+        let mut platforms = self.posts.platforms();
+        platforms.extend(self.aliases.iter().map(|alias| alias.platform));
+        platforms
+    }
+    fn tags(&self) -> Vec<TagId> {
+        self.posts.tags()
+    }
+    fn file_metas(&self) -> Vec<FileMetaId> {
+        self.posts.file_metas()
+    }
+}
+
+// `PostResponse` leaves its relation ids to `WithRelations`, a bundle needs them per post
+#[derive(Debug, Serialize)]
+pub struct BundlePost {
+    #[serde(flatten)]
+    pub post: PostResponse,
+    pub authors: Vec<AuthorId>,
+    pub tags: Vec<TagId>,
+    pub collections: Vec<CollectionId>,
+    pub files: Vec<FileMetaId>,
+}
+
+impl RequireRelations for BundlePost {
+    fn authors(&self) -> Vec<AuthorId> {
+        self.post.authors()
+    }
+    fn collections(&self) -> Vec<CollectionId> {
+        self.post.collections()
+    }
+    fn platforms(&self) -> Vec<PlatformId> {
+        self.post.platforms()
+    }
+    fn tags(&self) -> Vec<TagId> {
+        self.post.tags()
+    }
+    fn file_metas(&self) -> Vec<FileMetaId> {
+        self.post.file_metas()
+    }
+}
+
+pub struct Bundle {
+    manifest: Vec<u8>,
+    // archive path and the path inside the bundle
+    files: Vec<(PathBuf, String)>,
+}
+
+pub fn bundle_path(id: FileMetaId, filename: &str) -> String {
+    format!("files/{}/{filename}", id.0)
+}
+
+async fn export_post_handler(
+    Path(id): Path<PostId>,
+    State(state): State<AppState>,
+) -> ApiResult<Response> {
+    let bundle = state
+        .read(move |manager| {
+            manager
+                .get_post(id)?
+                .ok_or_else(|| ApiError::not_found(Post::ROUTE, id.0))?;
+            prepare(manager, &[id])
+        })
+        .await?;
+    Ok(stream_bundle(bundle, format!("post-{}", id.0)))
+}
+
+async fn export_category_handler<T: Category>(
+    Path(id): Path<u32>,
+    State(state): State<AppState>,
+) -> ApiResult<Response> {
+    let bundle = state
+        .read(move |manager| {
+            T::get_single(manager, id.into())?.ok_or_else(|| ApiError::not_found(T::ROUTE, id))?;
+            let ids: Vec<PostId> = T::filter_posts(manager.posts(), id.into())
+                .query::<PostShortResponse>()?
+                .into_iter()
+                .map(|post| post.id)
+                .collect();
+            prepare(manager, &ids)
+        })
+        .await?;
+    Ok(stream_bundle(bundle, format!("{}-{id}", T::ROUTE)))
+}
+
+async fn export_filter_handler(
+    Query(filter): Query<PostFilter>,
+    State(state): State<AppState>,
+) -> ApiResult<Response> {
+    let bundle = state
+        .read(move |manager| prepare(manager, &filter.select(manager, None)?))
+        .await?;
+    Ok(stream_bundle(bundle, "posts".to_string()))
+}
+
+pub fn prepare(manager: &PostArchiverManager, ids: &[PostId]) -> ApiResult<Bundle> {
+    let mut posts = Vec::with_capacity(ids.len());
+    for &id in ids {
+        let Some(post) = PostResponse::load(manager, id)? else {
+            continue;
+        };
+        posts.push(BundlePost {
+            authors: post.authors.clone(),
+            tags: post.tags.clone(),
+            collections: post.collections.clone(),
+            files: post.file_metas.clone(),
+            post,
+        });
+    }
+
+    let authors: HashSet<AuthorId> = posts.authors().into_iter().collect();
+    let mut aliases = vec![];
+    for author in authors {
+        aliases.extend(manager.bind(author).list_aliases()?);
+    }
+
+    let manifest = WithRelations::new(
+        manager,
+        BundleManifest {
+            version: BUNDLE_VERSION,
+            exported: Utc::now(),
+            posts,
+            aliases,
+        },
+    )?;
+
+    let files = manifest
+        .file_metas
+        .iter()
+        .map(|file_meta| {
+            (
+                manager.path.join(file_meta.path()),
+                bundle_path(file_meta.id, &file_meta.filename),
+            )
+        })
+        .collect();
+    let manifest = serde_json::to_vec_pretty(&manifest).map_err(ApiError::internal)?;
+    Ok(Bundle { manifest, files })
+}
+
+pub fn write_bundle<W: Write>(bundle: &Bundle, writer: W) -> io::Result<W> {
+    let mut tar = TarWriter::new(writer);
+    tar.append_bytes(MANIFEST, &bundle.manifest)?;
+    for (source, path) in &bundle.files {
+        let mut file = match fs::File::open(source) {
+            Ok(file) => file,
+            Err(err) => {
+                warn!("skipping {} in bundle: {err}", source.display());
+                continue;
+            }
+        };
+        let size = file.metadata()?.len();
+        tar.append(path, size, &mut file)?;
+    }
+    tar.finish()
+}
+
+pub fn export_to(manager: &PostArchiverManager, ids: &[PostId], output: &FsPath) -> ApiResult<()> {
+    let bundle = prepare(manager, ids)?;
+    let file = fs::File::create(output)?;
+    write_bundle(&bundle, io::BufWriter::new(file))?.flush()?;
+    Ok(())
+}
+
+// the archive is written on a blocking thread and handed to the body in chunks
+fn stream_bundle(bundle: Bundle, name: String) -> Response {
+    let (tx, rx) = mpsc::channel(8);
+    tokio::task::spawn_blocking(move || {
+        let writer = ChannelWriter {
+            tx: tx.clone(),
+            buf: vec![],
+        };
+        if let Err(err) = write_bundle(&bundle, writer) {
+            error!("failed to write bundle: {err}");
+            tx.blocking_send(Err(err)).ok();
+        }
+    });
+
+    let stream = futures_util::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    });
+    (
+        [
+            (header::CONTENT_TYPE, "application/x-tar".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{name}.tar\""),
+            ),
+        ],
+        Body::from_stream(stream),
+    )
+        .into_response()
+}
+
+struct ChannelWriter {
+    tx: mpsc::Sender<io::Result<Bytes>>,
+    buf: Vec<u8>,
+}
+
+impl ChannelWriter {
+    const CHUNK: usize = 64 * 1024;
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() >= Self::CHUNK {
+            self.flush()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let chunk = Bytes::from(mem::take(&mut self.buf));
+        // the receiver is gone once the client disconnects
+        self.tx
+            .blocking_send(Ok(chunk))
+            .map_err(|_| io::ErrorKind::BrokenPipe.into())
+    }
+}
@@ -0,0 +1,111 @@
+use axum::{
+    extract::{
+        FromRequest, FromRequestParts,
+        rejection::{JsonRejection, PathRejection},
+    },
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::QueryRejection;
+use serde::Serialize;
+
+use super::error::{ApiError, ApiErrorCode};
+
+// the axum extractors with their rejections turned into `ApiError` bodies
+
+#[derive(Debug, Clone, Copy, Default, FromRequest)]
+#[from_request(via(axum::Json), rejection(ApiError))]
+pub struct Json<T>(pub T);
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
+
+#[derive(Debug, FromRequestParts)]
+#[from_request(via(axum::extract::Path), rejection(ApiError))]
+pub struct Path<T>(pub T);
+
+#[derive(Debug, FromRequestParts)]
+#[from_request(via(axum_extra::extract::Query), rejection(ApiError))]
+pub struct Query<T>(pub T);
+
+fn rejected(status: StatusCode, message: String) -> ApiError {
+    let code = if status.is_server_error() {
+        ApiErrorCode::Internal
+    } else {
+        ApiErrorCode::BadRequest
+    };
+    ApiError::new(code, message)
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        rejected(rejection.status(), rejection.body_text())
+    }
+}
+
+impl From<PathRejection> for ApiError {
+    fn from(rejection: PathRejection) -> Self {
+        rejected(rejection.status(), rejection.body_text())
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        rejected(rejection.status(), rejection.body_text())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        Router,
+        body::{Body, to_bytes},
+        http::{Request, StatusCode, header},
+        routing::post,
+    };
+    use std::collections::HashMap;
+
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    use super::{Json, Path, Query};
+
+    async fn handler(
+        Path(_): Path<u32>,
+        Query(_): Query<HashMap<String, u64>>,
+        Json(_): Json<Value>,
+    ) {
+    }
+
+    fn rejection(uri: &str, body: &str) -> (StatusCode, Value) {
+        let router = Router::new().route("/items/{id}", post(handler));
+        let request = Request::post(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let response = router.oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            (status, serde_json::from_slice(&body).unwrap())
+        })
+    }
+
+    #[test]
+    fn rejections_are_api_errors() {
+        for (uri, body) in [
+            ("/items/abc?limit=1", "{}"),
+            ("/items/1?limit=many", "{}"),
+            ("/items/1?limit=1", "{"),
+        ] {
+            let (status, body) = rejection(uri, body);
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{uri}");
+            assert_eq!(body["code"], "bad_request", "{uri}");
+            assert!(body["message"].is_string(), "{uri}");
+        }
+    }
+}
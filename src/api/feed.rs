@@ -0,0 +1,465 @@
+use std::collections::HashMap;
+
+use axum::{
+    Router,
+    extract::State,
+    http::header,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use chrono::{DateTime, Utc};
+use post_archiver::{
+    Author, Collection, Content, FileMeta, FileMetaId, Platform, Post, Tag,
+    manager::PostArchiverManager,
+    query::{Paginate, Query as _, SortDir, Sortable, post::PostSort},
+};
+use rusqlite::{OptionalExtension, params};
+use serde::Deserialize;
+
+use super::{
+    AppState,
+    category::Category,
+    error::{ApiError, ApiResult},
+    extract::{Path, Query},
+    post::{PostResponse, PostShortResponse},
+    relation::WithRelations,
+    site::{escape, url_path},
+};
+
+pub fn wrap_feed_route(router: Router<AppState>) -> Router<AppState> {
+    router
+        .route("/feeds/posts", get(posts_feed_handler))
+        .route(
+            &format!("/feeds/{}/{{id}}", Author::ROUTE),
+            get(category_feed_handler::<Author>),
+        )
+        .route(
+            &format!("/feeds/{}/{{id}}", Tag::ROUTE),
+            get(category_feed_handler::<Tag>),
+        )
+        .route(
+            &format!("/feeds/{}/{{id}}", Collection::ROUTE),
+            get(category_feed_handler::<Collection>),
+        )
+        .route(
+            &format!("/feeds/{}/{{id}}", Platform::ROUTE),
+            get(category_feed_handler::<Platform>),
+        )
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeedFormat {
+    #[default]
+    Atom,
+    Rss,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct FeedOptions {
+    #[serde(default)]
+    pub format: FeedFormat,
+    pub limit: Option<u64>,
+}
+
+impl FeedOptions {
+    const MAX_LIMIT: u64 = 200;
+
+    fn limit(&self) -> u64 {
+        self.limit.unwrap_or(50).clamp(1, Self::MAX_LIMIT)
+    }
+}
+
+// links in a feed must be absolute, they are built on the configured public url
+// rather than request headers a client could forge
+struct Feed {
+    title: String,
+    // frontend page the feed mirrors, relative to the base url
+    page: String,
+    entries: Vec<WithRelations<PostResponse>>,
+}
+
+async fn posts_feed_handler(
+    Query(options): Query<FeedOptions>,
+    State(state): State<AppState>,
+) -> ApiResult<Response> {
+    let (limit, format) = (options.limit(), options.format);
+    let feed = state
+        .read(move |manager| {
+            Ok(Feed {
+                title: "Posts".to_string(),
+                page: format!("/{}", Post::ROUTE),
+                entries: entries::<Post>(manager, None, limit)?,
+            })
+        })
+        .await?;
+    Ok(render(state.public_url(), &feed, format))
+}
+
+async fn category_feed_handler<T: Category>(
+    Path(id): Path<u32>,
+    Query(options): Query<FeedOptions>,
+    State(state): State<AppState>,
+) -> ApiResult<Response> {
+    let (limit, format) = (options.limit(), options.format);
+    let feed = state
+        .read(move |manager| {
+            let name: String = manager
+                .conn()
+                .query_row(
+                    &format!("SELECT name FROM {} WHERE id = ?1", T::TABLE),
+                    params![id],
+                    |row| row.get(0),
+                )
+                .optional()?
+                .ok_or_else(|| ApiError::not_found(T::ROUTE, id))?;
+            Ok(Feed {
+                title: name,
+                page: format!("/{}/{id}", T::ROUTE),
+                entries: entries::<T>(manager, Some(id.into()), limit)?,
+            })
+        })
+        .await?;
+    Ok(render(state.public_url(), &feed, format))
+}
+
+// newest additions first, the same query the category pages filter with
+fn entries<T: Category>(
+    manager: &PostArchiverManager,
+    id: Option<T::Id>,
+    limit: u64,
+) -> ApiResult<Vec<WithRelations<PostResponse>>> {
+    let query = manager.posts();
+    let query = match id {
+        Some(id) => T::filter_posts(query, id),
+        None => query,
+    };
+    let posts = query
+        .sort(PostSort::Id, SortDir::Desc)
+        .pagination(limit, 0)
+        .query::<PostShortResponse>()?;
+
+    let mut entries = Vec::with_capacity(posts.len());
+    for post in posts {
+        if let Some(post) = PostResponse::load(manager, post.id)? {
+            let mut entry = WithRelations::new(manager, post)?;
+            // the thumb and file blocks may point at files another post holds
+            let shown: Vec<FileMetaId> = entry
+                .inner
+                .thumb
+                .into_iter()
+                .chain(entry.inner.content.iter().filter_map(|block| match block {
+                    Content::File(id) => Some(*id),
+                    Content::Text(_) => None,
+                }))
+                .collect();
+            for id in shown {
+                if entry.file_metas.iter().any(|file_meta| file_meta.id == id) {
+                    continue;
+                }
+                if let Some(file_meta) = manager.get_file_meta(id)? {
+                    entry.file_metas.push(file_meta);
+                }
+            }
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+fn file_url(base: &str, file_meta: &FileMeta) -> String {
+    let router = if file_meta.mime.starts_with("image/") {
+        "images"
+    } else {
+        "resource"
+    };
+    format!("{base}/{router}/{}", url_path(&file_meta.path()))
+}
+
+// text blocks are markdown source, so they are sent as preformatted paragraphs
+fn content_html(base: &str, post: &WithRelations<PostResponse>) -> String {
+    let file_metas: HashMap<FileMetaId, &FileMeta> =
+        post.file_metas.iter().map(|f| (f.id, f)).collect();
+
+    let mut html = String::new();
+    if let Some(thumb) = post.inner.thumb.and_then(|id| file_metas.get(&id)) {
+        html.push_str(&format!(
+            "<p><img src=\"{}\" alt=\"\"></p>\n",
+            escape(&file_url(base, thumb))
+        ));
+    }
+    for block in &post.inner.content {
+        match block {
+            Content::Text(text) => html.push_str(&format!(
+                "<p style=\"white-space: pre-wrap\">{}</p>\n",
+                escape(text)
+            )),
+            Content::File(id) => {
+                let Some(file_meta) = file_metas.get(id) else {
+                    continue;
+                };
+                let url = escape(&file_url(base, file_meta));
+                let filename = escape(&file_meta.filename);
+                if file_meta.mime.starts_with("image/") {
+                    html.push_str(&format!("<p><img src=\"{url}\" alt=\"{filename}\"></p>\n"));
+                } else {
+                    html.push_str(&format!("<p><a href=\"{url}\">{filename}</a></p>\n"));
+                }
+            }
+        }
+    }
+    html
+}
+
+fn render(base: &str, feed: &Feed, format: FeedFormat) -> Response {
+    let (content_type, body) = match format {
+        FeedFormat::Atom => ("application/atom+xml; charset=utf-8", atom(base, feed)),
+        FeedFormat::Rss => ("application/rss+xml; charset=utf-8", rss(base, feed)),
+    };
+    ([(header::CONTENT_TYPE, content_type)], body).into_response()
+}
+
+fn updated(feed: &Feed) -> DateTime<Utc> {
+    feed.entries
+        .iter()
+        .map(|entry| entry.inner.updated)
+        .max()
+        .unwrap_or_else(Utc::now)
+}
+
+fn atom(base: &str, feed: &Feed) -> String {
+    let link = escape(&format!("{base}{}", feed.page));
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+        <feed xmlns=\"http://www.w3.org/2005/Atom\">\n\
+        <id>{link}</id>\n<title>{}</title>\n<updated>{}</updated>\n\
+        <link href=\"{link}\"/>\n",
+        escape(&feed.title),
+        updated(feed).to_rfc3339(),
+    );
+
+    for entry in &feed.entries {
+        let post = &entry.inner;
+        let link = escape(&format!("{base}/{}/{}", Post::ROUTE, post.id.0));
+        xml.push_str(&format!(
+            "<entry>\n<id>{link}</id>\n<title>{}</title>\n<link href=\"{link}\"/>\n\
+            <published>{}</published>\n<updated>{}</updated>\n",
+            escape(&post.title),
+            post.published.to_rfc3339(),
+            post.updated.to_rfc3339(),
+        ));
+        for author in &entry.authors {
+            xml.push_str(&format!(
+                "<author><name>{}</name></author>\n",
+                escape(&author.name)
+            ));
+        }
+        for tag in &entry.tags {
+            xml.push_str(&format!("<category term=\"{}\"/>\n", escape(&tag.name)));
+        }
+        if let Some(thumb) = thumb(entry) {
+            xml.push_str(&format!(
+                "<link rel=\"enclosure\" type=\"{}\" href=\"{}\"/>\n",
+                escape(&thumb.mime),
+                escape(&file_url(base, thumb))
+            ));
+        }
+        xml.push_str(&format!(
+            "<content type=\"html\">{}</content>\n</entry>\n",
+            escape(&content_html(base, entry))
+        ));
+    }
+
+    xml.push_str("</feed>\n");
+    xml
+}
+
+fn rss(base: &str, feed: &Feed) -> String {
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+        <rss version=\"2.0\">\n<channel>\n\
+        <title>{}</title>\n<link>{}</link>\n<description>{}</description>\n\
+        <lastBuildDate>{}</lastBuildDate>\n",
+        escape(&feed.title),
+        escape(&format!("{base}{}", feed.page)),
+        escape(&feed.title),
+        updated(feed).to_rfc2822(),
+    );
+
+    for entry in &feed.entries {
+        let post = &entry.inner;
+        let link = escape(&format!("{base}/{}/{}", Post::ROUTE, post.id.0));
+        xml.push_str(&format!(
+            "<item>\n<title>{}</title>\n<link>{link}</link>\n\
+            <guid isPermaLink=\"true\">{link}</guid>\n<pubDate>{}</pubDate>\n",
+            escape(&post.title),
+            post.published.to_rfc2822(),
+        ));
+        for tag in &entry.tags {
+            xml.push_str(&format!("<category>{}</category>\n", escape(&tag.name)));
+        }
+        if let Some(thumb) = thumb(entry) {
+            // the length is required but unknown without touching the file
+            xml.push_str(&format!(
+                "<enclosure url=\"{}\" type=\"{}\" length=\"0\"/>\n",
+                escape(&file_url(base, thumb)),
+                escape(&thumb.mime)
+            ));
+        }
+        xml.push_str(&format!(
+            "<description>{}</description>\n</item>\n",
+            escape(&content_html(base, entry))
+        ));
+    }
+
+    xml.push_str("</channel>\n</rss>\n");
+    xml
+}
+
+fn thumb(entry: &WithRelations<PostResponse>) -> Option<&FileMeta> {
+    let thumb = entry.inner.thumb?;
+    entry
+        .file_metas
+        .iter()
+        .find(|file_meta| file_meta.id == thumb)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::extract::State;
+    use post_archiver::{
+        Content, PlatformId, Post, PostId, Tag,
+        importer::{UnsyncFileMeta, UnsyncPost, UnsyncTag},
+        manager::UpdatePost,
+    };
+
+    use super::{Feed, FeedOptions, atom, category_feed_handler, entries, rss};
+    use crate::api::{
+        AppState,
+        error::ApiErrorCode,
+        extract::{Path, Query},
+        site::url_path,
+        testing::TestArchive,
+    };
+
+    const BASE: &str = "https://example.com/archive";
+
+    fn post(archive: &TestArchive, title: &str) -> PostId {
+        let post = UnsyncPost::<()>::new(
+            PlatformId(0),
+            format!("https://example.com/{title}"),
+            title.to_string(),
+            vec![],
+        );
+        archive.import_post(post, false).unwrap().0
+    }
+
+    fn feed(archive: &TestArchive, limit: u64) -> Feed {
+        Feed {
+            title: "Tom & Jerry".to_string(),
+            page: "/posts".to_string(),
+            entries: entries::<Post>(archive, None, limit).unwrap(),
+        }
+    }
+
+    #[test]
+    fn escapes_titles_and_content() {
+        let archive = TestArchive::new();
+        let id = post(&archive, "<b>\"bold\" & more</b>");
+        let tag = archive
+            .import_tag(UnsyncTag {
+                name: "a<b".to_string(),
+                platform: None,
+            })
+            .unwrap();
+        archive.bind(id).add_tags(&[tag]).unwrap();
+        archive
+            .bind(id)
+            .update(UpdatePost::default().content(vec![Content::Text("<script>".to_string())]))
+            .unwrap();
+
+        let feed = feed(&archive, 50);
+        for xml in [atom(BASE, &feed), rss(BASE, &feed)] {
+            assert!(xml.contains("<title>Tom &amp; Jerry</title>"));
+            assert!(xml.contains("<title>&lt;b&gt;&quot;bold&quot; &amp; more&lt;/b&gt;</title>"));
+            assert!(xml.contains("a&lt;b"));
+            // content is html inside xml, escaped twice
+            assert!(xml.contains("&amp;lt;script&amp;gt;"));
+            assert!(!xml.contains("<script>"));
+        }
+    }
+
+    #[test]
+    fn links_are_absolute() {
+        let archive = TestArchive::new();
+        let id = post(&archive, "post");
+        let other = post(&archive, "other");
+        let file = archive
+            .import_file_meta(
+                other,
+                &UnsyncFileMeta::new("a b.png".to_string(), "image/png".to_string(), ()),
+            )
+            .unwrap();
+        archive
+            .bind(id)
+            .update(UpdatePost::default().content(vec![Content::File(file)]))
+            .unwrap();
+
+        let feed = feed(&archive, 50);
+        let link = format!("{BASE}/posts/{}", id.0);
+        let image = format!(
+            "{BASE}/images/{}/a%20b.png",
+            url_path(&Post::directory(other))
+        );
+
+        let xml = atom(BASE, &feed);
+        assert!(xml.contains(&format!("<link href=\"{BASE}/posts\"/>")));
+        assert!(xml.contains(&format!("<link href=\"{link}\"/>")));
+        assert!(xml.contains(&format!("src=&quot;{image}&quot;")));
+
+        let xml = rss(BASE, &feed);
+        assert!(xml.contains(&format!("<link>{BASE}/posts</link>")));
+        assert!(xml.contains(&format!("<guid isPermaLink=\"true\">{link}</guid>")));
+        assert!(xml.contains(&format!("src=&quot;{image}&quot;")));
+    }
+
+    #[test]
+    fn limits_are_clamped() {
+        let limit = |limit| FeedOptions {
+            limit,
+            ..Default::default()
+        };
+        assert_eq!(limit(None).limit(), 50);
+        assert_eq!(limit(Some(0)).limit(), 1);
+        assert_eq!(limit(Some(10)).limit(), 10);
+        assert_eq!(limit(Some(10_000)).limit(), FeedOptions::MAX_LIMIT);
+
+        let archive = TestArchive::new();
+        for title in ["a", "b", "c"] {
+            post(&archive, title);
+        }
+        let feed = feed(&archive, 2);
+        let titles: Vec<&str> = feed
+            .entries
+            .iter()
+            .map(|e| e.inner.title.as_str())
+            .collect();
+        assert_eq!(titles, ["c", "b"]);
+    }
+
+    #[test]
+    fn unknown_categories_are_not_found() {
+        let archive = TestArchive::new();
+        let state = AppState::new(archive.path.clone(), 1, true).unwrap();
+        let result =
+            tokio::runtime::Runtime::new()
+                .unwrap()
+                .block_on(category_feed_handler::<Tag>(
+                    Path(404),
+                    Query(FeedOptions::default()),
+                    State(state),
+                ));
+        let err = result.expect_err("an unknown tag had a feed");
+        assert_eq!(err.code, ApiErrorCode::NotFound);
+    }
+}
@@ -1,81 +1,328 @@
+use std::{
+    collections::HashSet,
+    path::{Path as FsPath, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
 use axum::{
     Router,
-    extract::{DefaultBodyLimit, Multipart, Path, State},
+    extract::{DefaultBodyLimit, Multipart, State, multipart::Field},
     http::StatusCode,
     routing::{delete, put},
 };
-use post_archiver::{FileMetaId, PostId, importer::UnsyncFileMeta};
+use post_archiver::{
+    FileMeta, FileMetaId, Post, PostId, importer::UnsyncFileMeta, manager::PostArchiverManager,
+};
+use rusqlite::params;
+use tokio::{fs, io::AsyncWriteExt};
 use tracing::{error, warn};
 
-use super::AppState;
+use super::{
+    AppState,
+    category::Category,
+    error::{ApiError, ApiResult},
+    extract::Path,
+    history::{self, Tracked},
+};
+
+pub(super) const TEMP_DIR: &str = ".upload";
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+const MAX_FILENAME_LEN: usize = 255;
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
 
 pub fn wrap_file_route(router: Router<AppState>) -> Router<AppState> {
-    const SIZE: usize = 128 * 1024 * 1024; // 128 MB
+    // fields are streamed to disk, so the limit only guards the archive size
+    const SIZE: usize = 2 * 1024 * 1024 * 1024; // 2 GB
 
     router
         .route("/posts/{id}/files", put(upload_file_handler))
         .layer(DefaultBodyLimit::max(SIZE))
         .route("/files/{id}", delete(remove_file_handler))
+        .merge(FileMeta::wrap_history_route(Router::new(), "/files"))
 }
 
 async fn upload_file_handler(
     Path(id): Path<PostId>,
     State(state): State<AppState>,
     mut multipart: Multipart,
-) -> StatusCode {
-    while let Some(field) = multipart.next_field().await.unwrap() {
-        // !WARNING: this is not safe, the filename can be something like "../../etc/passwd"
-        // TODO: sanitize the filename
+) -> ApiResult<StatusCode> {
+    let mut taken = state
+        .read(move |manager| {
+            manager
+                .get_post(id)?
+                .ok_or_else(|| ApiError::not_found(Post::ROUTE, id.0))?;
+            Ok(list_post_filenames(manager, id)?)
+        })
+        .await?;
+
+    fs::create_dir_all(state.path().join(TEMP_DIR)).await?;
+
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|err| ApiError::bad_request(err).with_field("files"))?
+    {
         let Some(filename) = field.file_name().map(|s| s.to_string()) else {
             warn!("invalid filename in multipart form data");
             continue;
         };
+        let Some(filename) = sanitize_filename(&filename) else {
+            return Err(
+                ApiError::bad_request(format!("unsafe filename: {filename:?}"))
+                    .with_field("filename"),
+            );
+        };
+        let filename = resolve_collision(filename, &taken);
+
         let mime = field
             .content_type()
             .unwrap_or("application/octet-stream")
             .to_string();
 
-        let data = match field.bytes().await {
-            Ok(data) => data.to_vec(),
-            Err(err) => {
-                warn!("failed to read multipart form data: {err}");
-                continue;
-            }
-        };
+        let temp = TempFile(temp_path(state.path(), &id.0.to_string()));
+        stream_to_file(field, &temp.0).await?;
+        commit_file(&state, id, &filename, mime, &temp.0).await?;
+        taken.insert(filename);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
 
-        let file_meta = UnsyncFileMeta::new(filename, mime, data);
-        if let Err(err) = state
-            .manager()
-            .import_file_meta_with_content(id, &file_meta)
-        {
-            error!("failed to import file meta: {err}");
-            return StatusCode::INTERNAL_SERVER_ERROR;
+pub(super) fn temp_path(root: &FsPath, prefix: &str) -> PathBuf {
+    root.join(TEMP_DIR).join(format!(
+        "{prefix}-{}.part",
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+// removed when dropped, which also covers requests the client aborted mid-upload
+pub(super) struct TempFile(pub PathBuf);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let removed = if self.0.is_dir() {
+            std::fs::remove_dir_all(&self.0)
+        } else {
+            std::fs::remove_file(&self.0)
         };
+        if let Err(err) = removed
+            && err.kind() != std::io::ErrorKind::NotFound
+        {
+            warn!("failed to remove {}: {err}", self.0.display());
+        }
+    }
+}
+
+pub(super) async fn stream_to_file(mut field: Field<'_>, path: &FsPath) -> std::io::Result<()> {
+    let mut file = fs::File::create(path).await?;
+    while let Some(chunk) = field
+        .chunk()
+        .await
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?
+    {
+        file.write_all(&chunk).await?;
     }
-    StatusCode::NO_CONTENT
+    file.flush().await
+}
+
+// the meta and the move share a transaction, a failed rename leaves no meta behind
+async fn commit_file(
+    state: &AppState,
+    post: PostId,
+    filename: &str,
+    mime: String,
+    temp: &FsPath,
+) -> ApiResult<()> {
+    let file_meta = UnsyncFileMeta::new(filename.to_string(), mime, temp.to_path_buf());
+    state
+        .transaction(move |manager| {
+            manager.import_file_meta_by_rename(post, &file_meta)?;
+            Ok(())
+        })
+        .await
+}
+
+pub(super) fn list_post_filenames(
+    manager: &PostArchiverManager,
+    post: PostId,
+) -> rusqlite::Result<HashSet<String>> {
+    let mut stmt = manager
+        .conn()
+        .prepare_cached("SELECT filename FROM file_metas WHERE post = ?1")?;
+    stmt.query_map(params![post.0], |row| row.get(0))?.collect()
+}
+
+pub(super) fn sanitize_filename(name: &str) -> Option<String> {
+    let name = name.trim().trim_end_matches(['.', ' ']);
+
+    if name.is_empty()
+        || name.starts_with('.')
+        || name.contains(['/', '\\'])
+        || name.chars().any(char::is_control)
+    {
+        return None;
+    }
+
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '|' | '?' | '*' => '_',
+            c => c,
+        })
+        .collect();
+
+    let stem = name.split('.').next().unwrap_or_default();
+    if RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem.trim()))
+    {
+        return None;
+    }
+
+    Some(truncate_filename(name))
+}
+
+fn truncate_filename(name: String) -> String {
+    if name.len() <= MAX_FILENAME_LEN {
+        return name;
+    }
+
+    let path = PathBuf::from(&name);
+    let ext = path
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .filter(|ext| ext.len() < MAX_FILENAME_LEN / 2)
+        .unwrap_or_default();
+    let mut stem = name[..name.len() - ext.len()].to_string();
+    while stem.len() + ext.len() > MAX_FILENAME_LEN {
+        stem.pop();
+    }
+    stem + &ext
+}
+
+pub(super) fn resolve_collision(name: String, taken: &HashSet<String>) -> String {
+    if !taken.contains(&name) {
+        return name;
+    }
+
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem, format!(".{ext}")),
+        _ => (name.as_str(), String::new()),
+    };
+    (1..)
+        .map(|n| {
+            // the stem gives way to the suffix, so a name at the limit still changes
+            let suffix = format!(" ({n}){ext}");
+            let mut stem = stem.to_string();
+            while !stem.is_empty() && stem.len() + suffix.len() > MAX_FILENAME_LEN {
+                stem.pop();
+            }
+            stem + &suffix
+        })
+        .find(|candidate| !taken.contains(candidate))
+        .unwrap()
 }
 
 async fn remove_file_handler(
     Path(id): Path<FileMetaId>,
     State(state): State<AppState>,
-) -> StatusCode {
-    let manager = state.manager();
-    let binded = manager.bind(id);
-    let Ok(file_meta) = binded.value() else {
-        warn!("file meta not found: {id}");
-        return StatusCode::NOT_FOUND;
+) -> ApiResult<StatusCode> {
+    let (path, trash) = state
+        .transaction(move |manager| {
+            let binded = manager.bind(id);
+            let Ok(file_meta) = binded.value() else {
+                return Err(ApiError::not_found("file", id.0));
+            };
+            let revision = history::record::<FileMeta>(manager, id.0, "delete")?;
+            binded.delete()?;
+            Ok((
+                manager.path.join(file_meta.path()),
+                history::trash_path(&manager.path, revision),
+            ))
+        })
+        .await?;
+
+    // keep the file around so the removal can be reverted
+    let moved = async {
+        if let Some(parent) = trash.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::rename(&path, &trash).await
     };
-    match binded.delete() {
-        Ok(_) => {
-            let path = manager.path.join(file_meta.path());
-            if let Err(err) = std::fs::remove_file(path) {
-                error!("failed to delete file: {err}");
-            }
-            StatusCode::NO_CONTENT
+    if let Err(err) = moved.await {
+        error!("failed to move deleted file to history: {err}");
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+impl Tracked for FileMeta {
+    const KIND: &'static str = "files";
+    const TABLE: &'static str = "file_metas";
+    const LINKS: &'static [(&'static str, &'static str)] = &[];
+    const REFS: &'static [(&'static str, &'static str)] = &[
+        ("posts", "thumb"),
+        ("authors", "thumb"),
+        ("collections", "thumb"),
+    ];
+
+    fn restored(manager: &PostArchiverManager, entity: u32, revision: i64) -> ApiResult<()> {
+        let trash = history::trash_path(&manager.path, revision);
+        if !trash.exists() {
+            return Ok(());
+        }
+
+        let Some(file_meta) = manager.get_file_meta(FileMetaId(entity))? else {
+            return Ok(());
+        };
+        let path = manager.path.join(file_meta.path());
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
         }
-        Err(err) => {
-            warn!("failed to delete file meta: {err}");
-            StatusCode::INTERNAL_SERVER_ERROR
+        std::fs::rename(trash, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{MAX_FILENAME_LEN, TempFile, resolve_collision, sanitize_filename};
+
+    #[test]
+    fn sanitize_rejects_paths() {
+        for name in [
+            "../a.png", "a/b.png", "a\\b.png", ".hidden", "..", "", "CON.txt",
+        ] {
+            assert_eq!(sanitize_filename(name), None, "{name:?} was accepted");
         }
+        assert_eq!(sanitize_filename("a:b?.png").as_deref(), Some("a_b_.png"));
+    }
+
+    #[test]
+    fn collisions_get_a_counter() {
+        let taken = HashSet::from(["a.png".to_string(), "a (1).png".to_string()]);
+        assert_eq!(resolve_collision("a.png".to_string(), &taken), "a (2).png");
+        assert_eq!(resolve_collision("b.png".to_string(), &taken), "b.png");
+    }
+
+    #[test]
+    fn collisions_at_the_length_limit_terminate() {
+        let name = format!("{}.png", "a".repeat(MAX_FILENAME_LEN - 4));
+        let taken = HashSet::from([name.clone()]);
+
+        let resolved = resolve_collision(name, &taken);
+        assert!(resolved.len() <= MAX_FILENAME_LEN);
+        assert!(resolved.ends_with(" (1).png"));
+    }
+
+    #[test]
+    fn temp_files_are_removed_on_drop() {
+        let path = std::env::temp_dir().join(format!("temp-file-test-{}", std::process::id()));
+        std::fs::write(&path, b"partial").unwrap();
+        drop(TempFile(path.clone()));
+        assert!(!path.exists());
     }
 }
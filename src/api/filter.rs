@@ -0,0 +1,600 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use post_archiver::{
+    AuthorId, CollectionId, PlatformId, PostId, TagId,
+    manager::PostArchiverManager,
+    query::{Query as _, Totalled},
+};
+use rusqlite::{params_from_iter, types::Value as SqlValue};
+use serde::{
+    Deserialize, Deserializer,
+    de::{Error as _, IntoDeserializer},
+};
+use serde_json::Value;
+use ts_rs::TS;
+
+use super::{
+    error::ApiResult,
+    post::PostShortResponse,
+    search,
+    utils::{Pagination, SortDirection, escape_like},
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, TS)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum MatchMode {
+    #[default]
+    All,
+    Any,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, TS)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum MissingRelation {
+    Author,
+    Tag,
+    Collection,
+    Platform,
+    Thumb,
+    Files,
+}
+
+impl MissingRelation {
+    fn sql(self) -> &'static str {
+        match self {
+            MissingRelation::Author => "p.id NOT IN (SELECT post FROM author_posts)",
+            MissingRelation::Tag => "p.id NOT IN (SELECT post FROM post_tags)",
+            MissingRelation::Collection => "p.id NOT IN (SELECT post FROM collection_posts)",
+            MissingRelation::Platform => "p.platform IS NULL",
+            MissingRelation::Thumb => "p.thumb IS NULL",
+            MissingRelation::Files => "p.id NOT IN (SELECT post FROM file_metas)",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, TS)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum PostSortKey {
+    #[default]
+    Id,
+    Title,
+    Published,
+    Updated,
+    // full-text rank, only meaningful together with `q`
+    Relevance,
+}
+
+impl PostSortKey {
+    fn sql(self) -> &'static str {
+        match self {
+            PostSortKey::Id => "p.id",
+            // bm25 ranks are negative and lower for better matches
+            PostSortKey::Relevance => "-s.rank",
+            PostSortKey::Title => "p.title COLLATE NOCASE",
+            PostSortKey::Published => "julianday(p.published)",
+            PostSortKey::Updated => "julianday(p.updated)",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, TS)]
+#[ts(export)]
+pub struct PostFilter {
+    #[serde(default)]
+    pub search: String,
+    #[serde(default)]
+    pub q: String,
+
+    #[serde(default)]
+    pub author: Vec<AuthorId>,
+    #[serde(default)]
+    pub tag: Vec<TagId>,
+    #[serde(default)]
+    pub collection: Vec<CollectionId>,
+    #[serde(default)]
+    pub platform: Vec<PlatformId>,
+
+    #[serde(default)]
+    pub exclude_author: Vec<AuthorId>,
+    #[serde(default)]
+    pub exclude_tag: Vec<TagId>,
+    #[serde(default)]
+    pub exclude_collection: Vec<CollectionId>,
+    #[serde(default)]
+    pub exclude_platform: Vec<PlatformId>,
+
+    #[serde(default)]
+    pub author_mode: MatchMode,
+    #[serde(default)]
+    pub tag_mode: MatchMode,
+    #[serde(default)]
+    pub collection_mode: MatchMode,
+
+    #[serde(default, deserialize_with = "deserialize_missing")]
+    pub missing: Vec<MissingRelation>,
+
+    #[serde(default, deserialize_with = "deserialize_date")]
+    pub published_after: Option<DateTime<Utc>>,
+    #[serde(default, deserialize_with = "deserialize_date")]
+    pub published_before: Option<DateTime<Utc>>,
+    #[serde(default, deserialize_with = "deserialize_date")]
+    pub updated_after: Option<DateTime<Utc>>,
+    #[serde(default, deserialize_with = "deserialize_date")]
+    pub updated_before: Option<DateTime<Utc>>,
+
+    // defaults to relevance when searching with `q`, to the id otherwise
+    #[serde(default)]
+    pub sort: Option<PostSortKey>,
+    #[serde(default)]
+    pub dir: SortDirection,
+}
+
+// accepts both repeated keys and comma separated values (`missing=author,tag`),
+// in JSON bodies as either an array or a single comma separated string
+fn deserialize_missing<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<MissingRelation>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Values {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    let values = match Values::deserialize(deserializer)? {
+        Values::One(value) => vec![value],
+        Values::Many(values) => values,
+    };
+    values
+        .iter()
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(|value| MissingRelation::deserialize(value.into_deserializer()))
+        .collect()
+}
+
+// accepts both RFC 3339 timestamps and plain `YYYY-MM-DD` dates (midnight UTC)
+fn deserialize_date<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<DateTime<Utc>>, D::Error> {
+    let Some(text) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(None);
+    }
+
+    if let Ok(date) = DateTime::parse_from_rfc3339(text) {
+        return Ok(Some(date.with_timezone(&Utc)));
+    }
+    NaiveDate::parse_from_str(text, "%Y-%m-%d")
+        .map(|date| Some(date.and_hms_opt(0, 0, 0).unwrap().and_utc()))
+        .map_err(|_| D::Error::custom(format!("invalid date {text:?}")))
+}
+
+#[derive(Debug, Default)]
+struct Conditions {
+    clauses: Vec<String>,
+    params: Vec<SqlValue>,
+}
+
+impl Conditions {
+    fn push(&mut self, clause: impl Into<String>, params: impl IntoIterator<Item = SqlValue>) {
+        self.clauses.push(clause.into());
+        self.params.extend(params);
+    }
+
+    fn relation(&mut self, table: &str, column: &str, ids: Vec<u32>, mode: MatchMode) {
+        if ids.is_empty() {
+            return;
+        }
+        let count = ids.len() as i64;
+        let ids = json_ids(ids);
+        match mode {
+            MatchMode::Any => self.push(
+                format!(
+                    "p.id IN (SELECT post FROM {table} WHERE {column} IN (SELECT value FROM json_each(?)))"
+                ),
+                [ids],
+            ),
+            MatchMode::All => self.push(
+                format!(
+                    "p.id IN (SELECT post FROM {table} WHERE {column} IN (SELECT value FROM json_each(?)) \
+                    GROUP BY post HAVING COUNT(DISTINCT {column}) = ?)"
+                ),
+                [ids, SqlValue::Integer(count)],
+            ),
+        }
+    }
+
+    fn exclude(&mut self, table: &str, column: &str, ids: Vec<u32>) {
+        if ids.is_empty() {
+            return;
+        }
+        self.push(
+            format!(
+                "p.id NOT IN (SELECT post FROM {table} WHERE {column} IN (SELECT value FROM json_each(?)))"
+            ),
+            [json_ids(ids)],
+        );
+    }
+
+    // `after` is inclusive, `before` is exclusive
+    fn range(&mut self, column: &str, after: Option<DateTime<Utc>>, before: Option<DateTime<Utc>>) {
+        if let Some(after) = after {
+            self.push(
+                format!("julianday({column}) >= julianday(?)"),
+                [SqlValue::Text(after.to_rfc3339())],
+            );
+        }
+        if let Some(before) = before {
+            self.push(
+                format!("julianday({column}) < julianday(?)"),
+                [SqlValue::Text(before.to_rfc3339())],
+            );
+        }
+    }
+
+    fn sql(&self) -> String {
+        if self.clauses.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", self.clauses.join(" AND "))
+        }
+    }
+}
+
+impl PostFilter {
+    fn conditions(&self) -> Conditions {
+        let mut conditions = Conditions::default();
+
+        let search = self.search.trim();
+        if !search.is_empty() {
+            conditions.push(
+                r"p.title LIKE '%' || ? || '%' ESCAPE '\'",
+                [SqlValue::Text(escape_like(search))],
+            );
+        }
+
+        let q = self.q.trim();
+        if !q.is_empty() {
+            conditions.push(
+                "p.id IN (SELECT rowid FROM editor.post_search WHERE post_search MATCH ?)",
+                [SqlValue::Text(q.to_string())],
+            );
+        }
+
+        conditions.relation(
+            "author_posts",
+            "author",
+            self.author.iter().map(|id| id.0).collect(),
+            self.author_mode,
+        );
+        conditions.relation(
+            "post_tags",
+            "tag",
+            self.tag.iter().map(|id| id.0).collect(),
+            self.tag_mode,
+        );
+        conditions.relation(
+            "collection_posts",
+            "collection",
+            self.collection.iter().map(|id| id.0).collect(),
+            self.collection_mode,
+        );
+        if !self.platform.is_empty() {
+            conditions.push(
+                "p.platform IN (SELECT value FROM json_each(?))",
+                [json_ids(self.platform.iter().map(|id| id.0).collect())],
+            );
+        }
+
+        conditions.exclude(
+            "author_posts",
+            "author",
+            self.exclude_author.iter().map(|id| id.0).collect(),
+        );
+        conditions.exclude(
+            "post_tags",
+            "tag",
+            self.exclude_tag.iter().map(|id| id.0).collect(),
+        );
+        conditions.exclude(
+            "collection_posts",
+            "collection",
+            self.exclude_collection.iter().map(|id| id.0).collect(),
+        );
+        if !self.exclude_platform.is_empty() {
+            conditions.push(
+                "(p.platform IS NULL OR p.platform NOT IN (SELECT value FROM json_each(?)))",
+                [json_ids(
+                    self.exclude_platform.iter().map(|id| id.0).collect(),
+                )],
+            );
+        }
+
+        for missing in &self.missing {
+            conditions.push(missing.sql(), []);
+        }
+
+        conditions.range("p.published", self.published_after, self.published_before);
+        conditions.range("p.updated", self.updated_after, self.updated_before);
+
+        conditions
+    }
+
+    fn sort(&self) -> PostSortKey {
+        match (self.sort, self.search_query()) {
+            (None | Some(PostSortKey::Relevance), Some(_)) => PostSortKey::Relevance,
+            (None | Some(PostSortKey::Relevance), None) => PostSortKey::Id,
+            (Some(sort), _) => sort,
+        }
+    }
+
+    fn order_by(&self) -> String {
+        let dir = self.dir.sql();
+        match self.sort() {
+            PostSortKey::Id => format!("p.id {dir}"),
+            sort => format!("{} {dir}, p.id {dir}", sort.sql()),
+        }
+    }
+
+    // true when the filter would select every post
+    pub fn is_empty(&self) -> bool {
+        self.conditions().clauses.is_empty()
+    }
+
+    pub fn search_query(&self) -> Option<&str> {
+        Some(self.q.trim()).filter(|q| !q.is_empty())
+    }
+
+    pub fn count(&self, manager: &PostArchiverManager) -> ApiResult<u64> {
+        if self.search_query().is_some() {
+            search::ensure_index(manager.conn())?;
+        }
+        let conditions = self.conditions();
+        manager
+            .conn()
+            .query_row(
+                &format!("SELECT COUNT(*) FROM posts p {}", conditions.sql()),
+                params_from_iter(conditions.params),
+                |row| row.get(0),
+            )
+            .map_err(|err| self.query_error(err))
+    }
+
+    pub fn select(
+        &self,
+        manager: &PostArchiverManager,
+        pagination: Option<&Pagination>,
+    ) -> ApiResult<Vec<PostId>> {
+        let conditions = self.conditions();
+        let mut params = vec![];
+        let mut from = "posts p".to_string();
+        if let Some(q) = self.search_query() {
+            search::ensure_index(manager.conn())?;
+            if self.sort() == PostSortKey::Relevance {
+                from.push_str(
+                    " JOIN (SELECT rowid AS post, rank FROM editor.post_search WHERE post_search MATCH ?) s \
+                    ON s.post = p.id",
+                );
+                params.push(SqlValue::Text(q.to_string()));
+            }
+        }
+        let mut sql = format!(
+            "SELECT p.id FROM {from} {} ORDER BY {}",
+            conditions.sql(),
+            self.order_by()
+        );
+        params.extend(conditions.params);
+        if let Some(pagination) = pagination {
+            sql.push_str(" LIMIT ? OFFSET ?");
+            params.extend([
+                SqlValue::Integer(pagination.limit() as i64),
+                SqlValue::Integer((pagination.limit() * pagination.page()) as i64),
+            ]);
+        }
+
+        let mut stmt = manager.conn().prepare(&sql)?;
+        stmt.query_map(params_from_iter(params), |row| row.get(0).map(PostId))
+            .and_then(|rows| rows.collect())
+            .map_err(|err| self.query_error(err))
+    }
+
+    pub fn query_page(
+        &self,
+        manager: &PostArchiverManager,
+        pagination: &Pagination,
+    ) -> ApiResult<Totalled<Vec<PostShortResponse>>> {
+        let total = self.count(manager)?;
+        let ids = self.select(manager, Some(pagination))?;
+        Ok(Totalled {
+            items: load_in_order(manager, &ids)?,
+            total,
+        })
+    }
+
+    fn query_error(&self, err: rusqlite::Error) -> super::error::ApiError {
+        search::query_error(err, &self.q)
+    }
+}
+
+pub fn load_in_order(
+    manager: &PostArchiverManager,
+    ids: &[PostId],
+) -> ApiResult<Vec<PostShortResponse>> {
+    if ids.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut query = manager.posts();
+    query.ids.extend(ids.iter().copied());
+    let mut items = query.query::<PostShortResponse>()?;
+    items.sort_by_key(|item| ids.iter().position(|id| *id == item.id));
+    Ok(items)
+}
+
+fn json_ids(ids: Vec<u32>) -> SqlValue {
+    SqlValue::Text(Value::from(ids).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{extract::FromRequestParts, http::Request};
+    use axum_extra::extract::Query;
+    use post_archiver::{
+        PlatformId, PostId,
+        importer::{UnsyncPost, UnsyncTag},
+    };
+    use serde_json::json;
+
+    use super::{MatchMode, MissingRelation, PostFilter, PostSortKey};
+    use crate::api::{error::ApiErrorCode, search, testing::TestArchive};
+
+    fn post(archive: &TestArchive, title: &str) -> PostId {
+        let post =
+            UnsyncPost::<()>::new(PlatformId(0), title.to_string(), title.to_string(), vec![]);
+        let (id, ..) = archive.import_post(post, false).unwrap();
+        search::index_post(archive, id).unwrap();
+        id
+    }
+
+    fn search(q: &str) -> PostFilter {
+        PostFilter {
+            q: q.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn missing_accepts_lists_and_comma_separated_values() {
+        let expected = vec![MissingRelation::Author, MissingRelation::Tag];
+        for body in [
+            json!({ "missing": "author,tag" }),
+            json!({ "missing": ["author", "tag"] }),
+            json!({ "missing": ["author,tag"] }),
+        ] {
+            let filter: PostFilter = serde_json::from_value(body).unwrap();
+            assert_eq!(filter.missing, expected);
+        }
+        for query in ["missing=author,tag", "missing=author&missing=tag"] {
+            let (mut parts, _) = Request::get(format!("/posts?{query}"))
+                .body(())
+                .unwrap()
+                .into_parts();
+            let Query(filter) = tokio::runtime::Runtime::new()
+                .unwrap()
+                .block_on(Query::<PostFilter>::from_request_parts(&mut parts, &()))
+                .unwrap();
+            assert_eq!(filter.missing, expected);
+        }
+
+        let body = json!({ "missing": "author,unknown" });
+        assert!(serde_json::from_value::<PostFilter>(body).is_err());
+    }
+
+    #[test]
+    fn filters_without_criteria_are_empty() {
+        let ordered = PostFilter {
+            sort: Some(PostSortKey::Title),
+            tag_mode: MatchMode::Any,
+            ..Default::default()
+        };
+        assert!(PostFilter::default().is_empty());
+        assert!(ordered.is_empty());
+
+        assert!(!search("apple").is_empty());
+        for body in [
+            json!({ "tag": [1] }),
+            json!({ "exclude_platform": [0] }),
+            json!({ "missing": "thumb" }),
+            json!({ "published_before": "2024-01-01" }),
+        ] {
+            let filter: PostFilter = serde_json::from_value(body.clone()).unwrap();
+            assert!(!filter.is_empty(), "{body}");
+        }
+    }
+
+    #[test]
+    fn relation_filters_match_all_or_any() {
+        let archive = TestArchive::new();
+        let tag = |name: &str| {
+            archive
+                .import_tag(UnsyncTag {
+                    name: name.to_string(),
+                    platform: None,
+                })
+                .unwrap()
+        };
+        let (a, b) = (tag("a"), tag("b"));
+        let both = post(&archive, "both");
+        let only_a = post(&archive, "only a");
+        let neither = post(&archive, "neither");
+        archive.bind(both).add_tags(&[a, b]).unwrap();
+        archive.bind(only_a).add_tags(&[a]).unwrap();
+
+        let select = |filter: PostFilter| {
+            let mut ids = filter.select(&archive, None).unwrap();
+            ids.sort_by_key(|id| id.0);
+            ids
+        };
+        let tags = PostFilter {
+            tag: vec![a, b],
+            ..Default::default()
+        };
+        assert_eq!(select(tags.clone()), vec![both]);
+        assert_eq!(
+            select(PostFilter {
+                tag_mode: MatchMode::Any,
+                ..tags
+            }),
+            vec![both, only_a]
+        );
+        assert_eq!(
+            select(PostFilter {
+                exclude_tag: vec![b],
+                ..Default::default()
+            }),
+            vec![only_a, neither]
+        );
+        assert_eq!(
+            select(PostFilter {
+                missing: vec![MissingRelation::Tag],
+                ..Default::default()
+            }),
+            vec![neither]
+        );
+    }
+
+    #[test]
+    fn search_results_keep_rank_order() {
+        let archive = TestArchive::new();
+        // newest first would put the weaker match on top
+        let strong = post(&archive, "apple apple");
+        let weak = post(&archive, "apple pie with a long list of other ingredients");
+        post(&archive, "banana");
+
+        assert_eq!(
+            search("apple").select(&archive, None).unwrap(),
+            vec![strong, weak]
+        );
+        assert_eq!(search("apple").count(&archive).unwrap(), 2);
+    }
+
+    #[test]
+    fn search_without_an_index_is_a_clear_error() {
+        let archive = TestArchive::new();
+        archive
+            .conn()
+            .execute_batch("DROP TABLE editor.post_search")
+            .unwrap();
+
+        let err = search("apple").select(&archive, None).unwrap_err();
+        assert_eq!(err.code, ApiErrorCode::ReadOnly);
+        assert!(PostFilter::default().select(&archive, None).is_ok());
+    }
+}
@@ -0,0 +1,555 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path as FsPath, PathBuf},
+};
+
+use axum::{
+    Router,
+    extract::State,
+    http::StatusCode,
+    routing::{get, post},
+};
+use chrono::{DateTime, Utc};
+use post_archiver::{manager::PostArchiverManager, query::Totalled};
+use rusqlite::{
+    Connection, OptionalExtension, params,
+    types::{Type, Value as SqlValue, ValueRef},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use ts_rs::TS;
+
+use super::{
+    AppState,
+    error::{ApiError, ApiErrorCode, ApiResult},
+    extract::{Json, Path, Query},
+    state::has_editor_db,
+    utils::Pagination,
+};
+
+pub const TRASH_DIR: &str = ".history";
+
+pub trait Tracked: Sized + Send + 'static {
+    const KIND: &'static str;
+    const TABLE: &'static str;
+    // rows in other tables owned by the entity: (table, column pointing at the entity),
+    // rows with an id of their own are never dropped or moved on revert, only brought back
+    const LINKS: &'static [(&'static str, &'static str)];
+    // nullable references from other tables: (table, column pointing at the entity)
+    const REFS: &'static [(&'static str, &'static str)] = &[];
+
+    fn restored(_manager: &PostArchiverManager, _entity: u32, _revision: i64) -> ApiResult<()> {
+        Ok(())
+    }
+
+    fn wrap_history_route(router: Router<AppState>, route: &str) -> Router<AppState> {
+        router
+            .route(
+                &format!("{route}/{{id}}/history"),
+                get(list_history_handler::<Self>),
+            )
+            .route(
+                &format!("{route}/{{id}}/history/{{revision}}/revert"),
+                post(revert_history_handler::<Self>),
+            )
+    }
+}
+
+pub fn init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS editor.revisions (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            kind TEXT NOT NULL,
+            entity INTEGER NOT NULL,
+            action TEXT NOT NULL,
+            snapshot TEXT NOT NULL,
+            created TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS editor.revisions_entity ON revisions (kind, entity);",
+    )
+}
+
+pub fn trash_path(root: &FsPath, revision: i64) -> PathBuf {
+    root.join(TRASH_DIR).join(revision.to_string())
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct Snapshot {
+    #[ts(type = "Record<string, unknown> | null")]
+    pub row: Option<Map<String, Value>>,
+    #[ts(type = "Record<string, Record<string, unknown>[]>")]
+    pub links: BTreeMap<String, Vec<Map<String, Value>>>,
+    pub refs: BTreeMap<String, Vec<i64>>,
+}
+
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export)]
+pub struct Revision {
+    pub id: i64,
+    pub kind: String,
+    pub entity: u32,
+    pub action: String,
+    pub created: DateTime<Utc>,
+    pub snapshot: Snapshot,
+}
+
+pub fn record<T: Tracked>(
+    manager: &PostArchiverManager,
+    entity: u32,
+    action: &str,
+) -> ApiResult<i64> {
+    let conn = manager.conn();
+    let snapshot = capture::<T>(conn, entity)?;
+    let snapshot = serde_json::to_string(&snapshot).map_err(ApiError::internal)?;
+    conn.execute(
+        "INSERT INTO editor.revisions (kind, entity, action, snapshot, created) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![T::KIND, entity, action, snapshot, Utc::now().to_rfc3339()],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+pub fn list<T: Tracked>(
+    manager: &PostArchiverManager,
+    entity: u32,
+    pagination: &Pagination,
+) -> ApiResult<Totalled<Vec<Revision>>> {
+    let conn = manager.conn();
+    // a read-only editor that never ran with write access has no history yet
+    if !has_editor_db(conn)? {
+        return Ok(Totalled {
+            items: vec![],
+            total: 0,
+        });
+    }
+    let total = conn.query_row(
+        "SELECT COUNT(*) FROM editor.revisions WHERE kind = ?1 AND entity = ?2",
+        params![T::KIND, entity],
+        |row| row.get(0),
+    )?;
+
+    let mut stmt = conn.prepare(
+        "SELECT id, kind, entity, action, snapshot, created FROM editor.revisions
+        WHERE kind = ?1 AND entity = ?2 ORDER BY id DESC LIMIT ?3 OFFSET ?4",
+    )?;
+    let items = stmt
+        .query_map(
+            params![
+                T::KIND,
+                entity,
+                pagination.limit(),
+                pagination.limit() * pagination.page()
+            ],
+            read_revision,
+        )?
+        .collect::<rusqlite::Result<_>>()?;
+
+    Ok(Totalled { items, total })
+}
+
+pub fn revert<T: Tracked>(
+    manager: &PostArchiverManager,
+    entity: u32,
+    revision: i64,
+) -> ApiResult<()> {
+    let conn = manager.conn();
+    let target = conn
+        .query_row(
+            "SELECT id, kind, entity, action, snapshot, created FROM editor.revisions
+            WHERE id = ?1 AND kind = ?2 AND entity = ?3",
+            params![revision, T::KIND, entity],
+            read_revision,
+        )
+        .optional()?
+        .ok_or_else(|| {
+            ApiError::new(
+                ApiErrorCode::NotFound,
+                format!("revision {revision} not found"),
+            )
+            .with_field("revision")
+        })?;
+
+    record::<T>(manager, entity, "revert")?;
+    restore::<T>(conn, entity, &target.snapshot)?;
+    T::restored(manager, entity, revision)
+}
+
+fn read_revision(row: &rusqlite::Row) -> rusqlite::Result<Revision> {
+    let snapshot: String = row.get(4)?;
+    let created: String = row.get(5)?;
+    Ok(Revision {
+        id: row.get(0)?,
+        kind: row.get(1)?,
+        entity: row.get(2)?,
+        action: row.get(3)?,
+        // a snapshot that does not parse must not read as "the entity did not exist"
+        snapshot: serde_json::from_str(&snapshot).map_err(|err| {
+            rusqlite::Error::FromSqlConversionFailure(4, Type::Text, Box::new(err))
+        })?,
+        created: DateTime::parse_from_rfc3339(&created)
+            .map(|created| created.with_timezone(&Utc))
+            .map_err(|err| {
+                rusqlite::Error::FromSqlConversionFailure(5, Type::Text, Box::new(err))
+            })?,
+    })
+}
+
+fn capture<T: Tracked>(conn: &Connection, entity: u32) -> rusqlite::Result<Snapshot> {
+    let row = select_rows(conn, T::TABLE, "id", entity)?
+        .into_iter()
+        .next();
+
+    let mut links = BTreeMap::new();
+    for (table, column) in T::LINKS {
+        links.insert(
+            format!("{table}.{column}"),
+            select_rows(conn, table, column, entity)?,
+        );
+    }
+
+    let mut refs = BTreeMap::new();
+    for (table, column) in T::REFS {
+        let mut stmt = conn.prepare(&format!("SELECT id FROM {table} WHERE {column} = ?1"))?;
+        let ids = stmt
+            .query_map(params![entity], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        refs.insert(format!("{table}.{column}"), ids);
+    }
+
+    Ok(Snapshot { row, links, refs })
+}
+
+fn restore<T: Tracked>(
+    conn: &Connection,
+    entity: u32,
+    snapshot: &Snapshot,
+) -> rusqlite::Result<()> {
+    match &snapshot.row {
+        Some(row) => upsert(conn, T::TABLE, row)?,
+        None => {
+            conn.execute(
+                &format!("DELETE FROM {} WHERE id = ?1", T::TABLE),
+                params![entity],
+            )?;
+            return Ok(());
+        }
+    }
+
+    // only tables declared by the entity are touched, whatever the snapshot says
+    for (table, column) in T::LINKS {
+        let Some(rows) = snapshot.links.get(&format!("{table}.{column}")) else {
+            continue;
+        };
+
+        if !rows.iter().any(|row| row.contains_key("id")) {
+            conn.execute(
+                &format!("DELETE FROM {table} WHERE {column} = ?1"),
+                params![entity],
+            )?;
+            for row in rows {
+                replace(conn, table, row)?;
+            }
+        } else {
+            // rows deleted since the snapshot come back, the others stay with whatever
+            // holds them now, e.g. a file handed to another post still showing it
+            for row in rows {
+                insert_missing(conn, table, row)?;
+            }
+        }
+    }
+
+    for (table, column) in T::REFS {
+        let Some(ids) = snapshot.refs.get(&format!("{table}.{column}")) else {
+            continue;
+        };
+        reattach(
+            conn,
+            table,
+            column,
+            entity,
+            ids.iter().map(|&id| Value::from(id)).collect(),
+        )?;
+    }
+
+    Ok(())
+}
+
+fn reattach(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    entity: u32,
+    ids: Vec<Value>,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        &format!("UPDATE {table} SET {column} = ?1 WHERE id IN (SELECT value FROM json_each(?2))"),
+        params![entity, Value::from(ids).to_string()],
+    )?;
+    Ok(())
+}
+
+fn select_rows(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    entity: u32,
+) -> rusqlite::Result<Vec<Map<String, Value>>> {
+    let mut stmt = conn.prepare(&format!("SELECT * FROM {table} WHERE {column} = ?1"))?;
+    let names: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+    stmt.query_map(params![entity], |row| {
+        names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| Ok((name.clone(), to_json(row.get_ref(i)?))))
+            .collect()
+    })?
+    .collect()
+}
+
+// a link row replaces whatever took its unique key meanwhile, e.g. an alias
+// whose platform was deleted and fell back to the unknown one
+fn replace(conn: &Connection, table: &str, row: &Map<String, Value>) -> rusqlite::Result<()> {
+    let (columns, values) = columns_of(row);
+    conn.execute(
+        &format!(
+            "INSERT OR REPLACE INTO {table} ({}) VALUES ({})",
+            columns.join(", "),
+            placeholders(columns.len())
+        ),
+        rusqlite::params_from_iter(values),
+    )?;
+    Ok(())
+}
+
+fn insert_missing(
+    conn: &Connection,
+    table: &str,
+    row: &Map<String, Value>,
+) -> rusqlite::Result<()> {
+    let (columns, values) = columns_of(row);
+    conn.execute(
+        &format!(
+            "INSERT INTO {table} ({}) VALUES ({}) ON CONFLICT (id) DO NOTHING",
+            columns.join(", "),
+            placeholders(columns.len())
+        ),
+        rusqlite::params_from_iter(values),
+    )?;
+    Ok(())
+}
+
+fn upsert(conn: &Connection, table: &str, row: &Map<String, Value>) -> rusqlite::Result<()> {
+    let (columns, values) = columns_of(row);
+    let updates: Vec<String> = columns
+        .iter()
+        .filter(|column| *column != "\"id\"")
+        .map(|column| format!("{column} = excluded.{column}"))
+        .collect();
+    let conflict = if updates.is_empty() {
+        "DO NOTHING".to_string()
+    } else {
+        format!("DO UPDATE SET {}", updates.join(", "))
+    };
+    conn.execute(
+        &format!(
+            "INSERT INTO {table} ({}) VALUES ({}) ON CONFLICT (id) {conflict}",
+            columns.join(", "),
+            placeholders(columns.len())
+        ),
+        rusqlite::params_from_iter(values),
+    )?;
+    Ok(())
+}
+
+fn columns_of(row: &Map<String, Value>) -> (Vec<String>, Vec<SqlValue>) {
+    row.iter()
+        .map(|(column, value)| {
+            (
+                format!("\"{}\"", column.replace('"', "\"\"")),
+                from_json(value),
+            )
+        })
+        .unzip()
+}
+
+fn placeholders(len: usize) -> String {
+    (1..=len)
+        .map(|i| format!("?{i}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn to_json(value: ValueRef) -> Value {
+    match value {
+        ValueRef::Null => Value::Null,
+        ValueRef::Integer(i) => Value::from(i),
+        ValueRef::Real(f) => Value::from(f),
+        ValueRef::Text(text) => Value::from(String::from_utf8_lossy(text)),
+        ValueRef::Blob(blob) => Value::from(blob.to_vec()),
+    }
+}
+
+fn from_json(value: &Value) -> SqlValue {
+    match value {
+        Value::Null => SqlValue::Null,
+        Value::Bool(b) => SqlValue::Integer(*b as i64),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => SqlValue::Integer(i),
+            None => SqlValue::Real(n.as_f64().unwrap_or_default()),
+        },
+        Value::String(s) => SqlValue::Text(s.clone()),
+        Value::Array(bytes) => SqlValue::Blob(
+            bytes
+                .iter()
+                .filter_map(|b| b.as_u64().map(|b| b as u8))
+                .collect(),
+        ),
+        Value::Object(_) => SqlValue::Text(value.to_string()),
+    }
+}
+
+async fn list_history_handler<T: Tracked>(
+    Path(id): Path<u32>,
+    Query(pagination): Query<Pagination>,
+    State(state): State<AppState>,
+) -> ApiResult<Json<Totalled<Vec<Revision>>>> {
+    state
+        .read(move |manager| Ok(Json(list::<T>(manager, id, &pagination)?)))
+        .await
+}
+
+async fn revert_history_handler<T: Tracked>(
+    Path((id, revision)): Path<(u32, i64)>,
+    State(state): State<AppState>,
+) -> ApiResult<StatusCode> {
+    state
+        .transaction(move |manager| revert::<T>(manager, id, revision))
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use post_archiver::{
+        Platform, PlatformId, Post, PostId,
+        importer::{UnsyncAlias, UnsyncAuthor, UnsyncFileMeta, UnsyncPost, UnsyncTag},
+    };
+    use rusqlite::params;
+
+    use super::{list, record, revert};
+    use crate::api::testing::TestArchive;
+
+    fn post(archive: &TestArchive, platform: PlatformId) -> PostId {
+        let post = UnsyncPost::<()>::new(platform, "post".to_string(), "post".to_string(), vec![]);
+        archive.import_post(post, false).unwrap().0
+    }
+
+    #[test]
+    fn reverting_a_post_keeps_newer_files() {
+        let archive = TestArchive::new();
+        let post = post(&archive, PlatformId(0));
+        let file = |name: &str| {
+            let file_meta = UnsyncFileMeta::new(name.to_string(), "image/png".to_string(), ());
+            archive.import_file_meta(post, &file_meta).unwrap()
+        };
+        let older = file("older.png");
+
+        let revision = record::<Post>(&archive, post.0, "update").unwrap();
+        archive.bind(older).delete().unwrap();
+        let newer = file("newer.png");
+
+        revert::<Post>(&archive, post.0, revision).unwrap();
+
+        let mut files = archive.bind(post).list_file_metas().unwrap();
+        files.sort_by_key(|file| file.0);
+        assert_eq!(files, vec![older, newer]);
+    }
+
+    #[test]
+    fn reverting_a_deleted_platform_restores_tags_and_aliases() {
+        let archive = TestArchive::new();
+        let platform = archive.import_platform("example".to_string()).unwrap();
+        let tag = archive
+            .import_tag(UnsyncTag {
+                name: "art".to_string(),
+                platform: Some(platform),
+            })
+            .unwrap();
+        let author = archive
+            .import_author(
+                UnsyncAuthor::new("jack".to_string())
+                    .aliases(vec![UnsyncAlias::new(platform, "jack".to_string())]),
+            )
+            .unwrap();
+        let post = post(&archive, platform);
+        archive.bind(post).add_tags(&[tag]).unwrap();
+
+        let revision = record::<Platform>(&archive, platform.0, "delete").unwrap();
+        archive.bind(platform).delete().unwrap();
+        assert!(archive.get_tag(tag).unwrap().is_none());
+
+        revert::<Platform>(&archive, platform.0, revision).unwrap();
+
+        assert_eq!(
+            archive.get_tag(tag).unwrap().unwrap().platform,
+            Some(platform)
+        );
+        assert_eq!(
+            archive.find_author_by_alias("jack", platform).unwrap(),
+            Some(author)
+        );
+        assert_eq!(
+            archive.find_author_by_alias("jack", PlatformId(0)).unwrap(),
+            None
+        );
+        assert_eq!(
+            archive.get_post(post).unwrap().unwrap().platform,
+            Some(platform)
+        );
+    }
+
+    #[test]
+    fn corrupt_snapshots_are_not_restored() {
+        let archive = TestArchive::new();
+        let post = post(&archive, PlatformId(0));
+        archive
+            .conn()
+            .execute(
+                "INSERT INTO editor.revisions (kind, entity, action, snapshot, created)
+                VALUES ('posts', ?1, 'update', '{', '2024-01-01T00:00:00Z')",
+                params![post.0],
+            )
+            .unwrap();
+        let revision = archive.conn().last_insert_rowid();
+
+        assert!(revert::<Post>(&archive, post.0, revision).is_err());
+        assert!(archive.get_post(post).unwrap().is_some());
+    }
+
+    #[test]
+    fn unknown_revisions_report_their_full_id() {
+        let archive = TestArchive::new();
+        let post = post(&archive, PlatformId(0));
+        let revision = i64::from(u32::MAX) + 2;
+
+        let err = revert::<Post>(&archive, post.0, revision).unwrap_err();
+        assert_eq!(err.message, format!("revision {revision} not found"));
+    }
+
+    #[test]
+    fn unparsable_dates_are_errors() {
+        let archive = TestArchive::new();
+        let post = post(&archive, PlatformId(0));
+        let revision = record::<Post>(&archive, post.0, "update").unwrap();
+        archive
+            .conn()
+            .execute(
+                "UPDATE editor.revisions SET created = 'yesterday' WHERE id = ?1",
+                params![revision],
+            )
+            .unwrap();
+
+        let pagination = serde_json::from_str("{}").unwrap();
+        assert!(list::<Post>(&archive, post.0, &pagination).is_err());
+    }
+}
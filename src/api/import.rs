@@ -0,0 +1,921 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, BufReader},
+    path::{Path as FsPath, PathBuf},
+};
+
+use axum::{
+    Router,
+    extract::{DefaultBodyLimit, Multipart, State},
+    routing::post,
+};
+use chrono::{DateTime, Utc};
+use post_archiver::{
+    Alias, AuthorId, CollectionId, Comment, Content, FileMetaId, PlatformId, Post, PostId, TagId,
+    importer::{
+        UnsyncAlias, UnsyncAuthor, UnsyncCollection, UnsyncFileMeta, UnsyncPost, UnsyncTag,
+    },
+    manager::{Binded, PostArchiverManager, UpdateAuthor, UpdateCollection, UpdatePost},
+};
+use rusqlite::{OptionalExtension, ToSql, params};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use super::{
+    AppState,
+    category::pending_source,
+    error::{ApiError, ApiResult},
+    export::{BUNDLE_VERSION, MANIFEST, bundle_path},
+    extract::{Json, Query},
+    file::{TEMP_DIR, TempFile, sanitize_filename, stream_to_file, temp_path},
+    history,
+    post::{MovedFile, move_file},
+    search,
+    tar::TarReader,
+};
+
+pub fn wrap_import_route(router: Router<AppState>) -> Router<AppState> {
+    const SIZE: usize = 2 * 1024 * 1024 * 1024; // 2 GB
+
+    router.route(
+        "/import",
+        post(import_handler).layer(DefaultBodyLimit::max(SIZE)),
+    )
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictPolicy {
+    // keep the post already in the archive
+    #[default]
+    Skip,
+    // replace the fields, relations and same-named files of the existing post
+    Overwrite,
+    // import it as a new post next to the existing one
+    Duplicate,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ImportOptions {
+    #[serde(default)]
+    pub policy: ConflictPolicy,
+    #[serde(default)]
+    pub preview: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, TS)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum ImportAction {
+    Created,
+    Matched,
+    Skipped,
+    Overwritten,
+}
+
+#[derive(Debug, Serialize, TS)]
+#[ts(export)]
+pub struct ImportedEntity {
+    // id inside the bundle
+    pub source: u32,
+    // id inside this archive, left out of previews for created entities
+    pub id: Option<u32>,
+    pub name: String,
+    pub action: ImportAction,
+}
+
+#[derive(Debug, Default, Serialize, TS)]
+#[ts(export)]
+pub struct ImportReport {
+    pub preview: bool,
+    pub posts: Vec<ImportedEntity>,
+    pub authors: Vec<ImportedEntity>,
+    pub tags: Vec<ImportedEntity>,
+    pub collections: Vec<ImportedEntity>,
+    pub platforms: Vec<ImportedEntity>,
+    pub files: usize,
+}
+
+// mirrors of the exported entities, only what is needed to recreate them
+#[derive(Debug, Deserialize)]
+struct ImportManifest {
+    version: u32,
+    posts: Vec<ImportPost>,
+    #[serde(default)]
+    aliases: Vec<Alias>,
+    #[serde(default)]
+    authors: Vec<ImportAuthor>,
+    #[serde(default)]
+    collections: Vec<ImportCollection>,
+    #[serde(default)]
+    platforms: Vec<ImportPlatform>,
+    #[serde(default)]
+    tags: Vec<ImportTag>,
+    #[serde(default)]
+    file_metas: Vec<ImportFileMeta>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ImportPost {
+    id: PostId,
+    title: String,
+    #[serde(default)]
+    content: Vec<Content>,
+    source: Option<String>,
+    updated: DateTime<Utc>,
+    published: DateTime<Utc>,
+    thumb: Option<FileMetaId>,
+    platform: Option<PlatformId>,
+    #[serde(default)]
+    comments: Vec<Comment>,
+    #[serde(default)]
+    authors: Vec<AuthorId>,
+    #[serde(default)]
+    tags: Vec<TagId>,
+    #[serde(default)]
+    collections: Vec<CollectionId>,
+    #[serde(default)]
+    files: Vec<FileMetaId>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ImportAuthor {
+    id: AuthorId,
+    name: String,
+    thumb: Option<FileMetaId>,
+    updated: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ImportCollection {
+    id: CollectionId,
+    name: String,
+    source: Option<String>,
+    thumb: Option<FileMetaId>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ImportPlatform {
+    id: PlatformId,
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ImportTag {
+    id: TagId,
+    name: String,
+    platform: Option<PlatformId>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ImportFileMeta {
+    id: FileMetaId,
+    filename: String,
+    mime: String,
+}
+
+async fn import_handler(
+    Query(options): Query<ImportOptions>,
+    State(state): State<AppState>,
+    mut multipart: Multipart,
+) -> ApiResult<Json<ImportReport>> {
+    let field = multipart
+        .next_field()
+        .await
+        .map_err(|err| ApiError::bad_request(err).with_field("bundle"))?
+        .ok_or_else(|| ApiError::bad_request("no bundle uploaded").with_field("bundle"))?;
+
+    tokio::fs::create_dir_all(state.path().join(TEMP_DIR)).await?;
+    let temp = TempFile(temp_path(state.path(), "import"));
+    stream_to_file(field, &temp.0).await?;
+    Ok(Json(import(&state, temp.0.clone(), options).await?))
+}
+
+pub async fn import(
+    state: &AppState,
+    bundle: PathBuf,
+    options: ImportOptions,
+) -> ApiResult<ImportReport> {
+    let ImportOptions { policy, preview } = options;
+
+    let manifest_path = bundle.clone();
+    let manifest = tokio::task::spawn_blocking(move || read_manifest(&manifest_path))
+        .await
+        .map_err(ApiError::internal)??;
+    if manifest.version > BUNDLE_VERSION {
+        return Err(ApiError::bad_request(format!(
+            "bundle version {} is newer than the supported {BUNDLE_VERSION}",
+            manifest.version
+        ))
+        .with_field("bundle"));
+    }
+
+    // files are unpacked before the transaction and only moved in once it is
+    // committed, a failed import leaves the archive directory untouched
+    let staging = TempFile(temp_path(state.path(), "import"));
+    let staged = if preview {
+        Staged::new()
+    } else {
+        let (bundle, dir, file_metas) = (bundle.clone(), staging.0.clone(), file_metas(&manifest));
+        tokio::task::spawn_blocking(move || stage_files(&bundle, &dir, &file_metas))
+            .await
+            .map_err(ApiError::internal)??
+    };
+
+    let (mut report, moved) = state
+        .transaction(move |manager| {
+            let conn = manager.conn();
+            // a preview runs the whole import and rolls it back
+            conn.execute_batch("SAVEPOINT import")?;
+            let result = apply(manager, &manifest, policy, staged);
+            if preview || result.is_err() {
+                conn.execute_batch("ROLLBACK TO import")?;
+            }
+            conn.execute_batch("RELEASE import")?;
+            result
+        })
+        .await?;
+
+    if !preview {
+        for file in &moved {
+            move_file(&file.from, &state.path().join(&file.to)).await;
+        }
+    }
+
+    report.preview = preview;
+    if preview {
+        for entity in report.entities_mut() {
+            if entity.action == ImportAction::Created {
+                entity.id = None;
+            }
+        }
+    }
+    Ok(report)
+}
+
+impl ImportReport {
+    fn entities_mut(&mut self) -> impl Iterator<Item = &mut ImportedEntity> {
+        self.posts
+            .iter_mut()
+            .chain(&mut self.authors)
+            .chain(&mut self.tags)
+            .chain(&mut self.collections)
+            .chain(&mut self.platforms)
+    }
+}
+
+fn read_manifest(bundle: &FsPath) -> ApiResult<ImportManifest> {
+    let mut reader = TarReader::new(BufReader::new(fs::File::open(bundle)?));
+    while let Some(entry) = reader.next_entry()? {
+        if entry.path == MANIFEST {
+            return serde_json::from_reader(entry).map_err(|err| {
+                ApiError::bad_request(format!("invalid bundle manifest: {err}"))
+                    .with_field("bundle")
+            });
+        }
+    }
+    Err(ApiError::bad_request(format!("bundle has no {MANIFEST}")).with_field("bundle"))
+}
+
+// bundle file id to the unpacked copy waiting to be moved into the archive
+type Staged = HashMap<FileMetaId, PathBuf>;
+
+fn file_metas(manifest: &ImportManifest) -> HashMap<String, FileMetaId> {
+    manifest
+        .file_metas
+        .iter()
+        .map(|file_meta| (bundle_path(file_meta.id, &file_meta.filename), file_meta.id))
+        .collect()
+}
+
+fn stage_files(
+    bundle: &FsPath,
+    staging: &FsPath,
+    file_metas: &HashMap<String, FileMetaId>,
+) -> ApiResult<Staged> {
+    fs::create_dir_all(staging)?;
+
+    let mut staged = Staged::new();
+    let mut reader = TarReader::new(BufReader::new(fs::File::open(bundle)?));
+    while let Some(mut entry) = reader.next_entry()? {
+        let Some(&id) = file_metas.get(&entry.path) else {
+            continue;
+        };
+        let path = staging.join(id.0.to_string());
+        io::copy(&mut entry, &mut fs::File::create(&path)?)?;
+        staged.insert(id, path);
+    }
+    Ok(staged)
+}
+
+fn find_id(
+    manager: &PostArchiverManager,
+    sql: &str,
+    params: &[&dyn ToSql],
+) -> rusqlite::Result<Option<u32>> {
+    manager
+        .conn()
+        .query_row(sql, params, |row| row.get(0))
+        .optional()
+}
+
+fn apply(
+    manager: &PostArchiverManager,
+    manifest: &ImportManifest,
+    policy: ConflictPolicy,
+    mut staged: Staged,
+) -> ApiResult<(ImportReport, Vec<MovedFile>)> {
+    let mut report = ImportReport::default();
+    let mut moved = vec![];
+
+    let mut platforms = HashMap::new();
+    for platform in &manifest.platforms {
+        let (id, action) = match manager.find_platform(&platform.name)? {
+            Some(id) => (id, ImportAction::Matched),
+            None => (
+                manager.import_platform(platform.name.clone())?,
+                ImportAction::Created,
+            ),
+        };
+        platforms.insert(platform.id, id);
+        report.platforms.push(ImportedEntity {
+            source: platform.id.0,
+            id: Some(id.0),
+            name: platform.name.clone(),
+            action,
+        });
+    }
+    let platform_of = |id: Option<PlatformId>| id.and_then(|id| platforms.get(&id).copied());
+
+    // tag names are unique across platforms, so a name alone identifies the tag
+    let mut tags = HashMap::new();
+    for tag in &manifest.tags {
+        let (id, action) = match find_id(
+            manager,
+            "SELECT id FROM tags WHERE name = ?1",
+            params![tag.name],
+        )? {
+            Some(id) => (TagId(id), ImportAction::Matched),
+            None => (
+                manager.import_tag(UnsyncTag {
+                    name: tag.name.clone(),
+                    platform: platform_of(tag.platform),
+                })?,
+                ImportAction::Created,
+            ),
+        };
+        tags.insert(tag.id, id);
+        report.tags.push(ImportedEntity {
+            source: tag.id.0,
+            id: Some(id.0),
+            name: tag.name.clone(),
+            action,
+        });
+    }
+
+    let mut collections = HashMap::new();
+    for collection in &manifest.collections {
+        let existing = match &collection.source {
+            Some(source) => manager.find_collection_by_source(source)?,
+            None => find_id(
+                manager,
+                "SELECT id FROM collections WHERE name = ?1 AND source IS NULL",
+                params![collection.name],
+            )?
+            .map(CollectionId),
+        };
+        let (id, action) = match existing {
+            Some(id) => (id, ImportAction::Matched),
+            None => {
+                let id = manager.import_collection(UnsyncCollection::new(
+                    collection.name.clone(),
+                    (collection.source.clone()).unwrap_or_else(|| pending_source("collection")),
+                ))?;
+                manager
+                    .bind(id)
+                    .update(UpdateCollection::default().source(collection.source.clone()))?;
+                (id, ImportAction::Created)
+            }
+        };
+        collections.insert(collection.id, id);
+        report.collections.push(ImportedEntity {
+            source: collection.id.0,
+            id: Some(id.0),
+            name: collection.name.clone(),
+            action,
+        });
+    }
+
+    let mut authors = HashMap::new();
+    for author in &manifest.authors {
+        let aliases: Vec<UnsyncAlias> = manifest
+            .aliases
+            .iter()
+            .filter(|alias| alias.target == author.id)
+            .map(|alias| {
+                // bundles written before alias platforms were exported fall back to unknown
+                let platform = platforms
+                    .get(&alias.platform)
+                    .copied()
+                    .unwrap_or(PlatformId(0));
+                UnsyncAlias {
+                    link: alias.link.clone(),
+                    ..UnsyncAlias::new(platform, alias.source.clone())
+                }
+            })
+            .collect();
+
+        // alias sources are unique across platforms
+        let mut existing = None;
+        for alias in &aliases {
+            existing = find_id(
+                manager,
+                "SELECT target FROM author_aliases WHERE source = ?1",
+                params![alias.source],
+            )?;
+            if existing.is_some() {
+                break;
+            }
+        }
+
+        let (id, action) = match existing {
+            Some(id) => (AuthorId(id), ImportAction::Matched),
+            None => (
+                manager.import_author(
+                    UnsyncAuthor::new(author.name.clone())
+                        .updated(author.updated)
+                        .aliases(aliases),
+                )?,
+                ImportAction::Created,
+            ),
+        };
+        authors.insert(author.id, id);
+        report.authors.push(ImportedEntity {
+            source: author.id.0,
+            id: Some(id.0),
+            name: author.name.clone(),
+            action,
+        });
+    }
+
+    let file_metas: HashMap<FileMetaId, &ImportFileMeta> = manifest
+        .file_metas
+        .iter()
+        .map(|file_meta| (file_meta.id, file_meta))
+        .collect();
+    let mut files = HashMap::new();
+
+    for post in &manifest.posts {
+        let existing = match &post.source {
+            Some(source) => manager.find_post(source)?,
+            None => None,
+        };
+
+        let (id, action) = match (existing, policy) {
+            (Some(id), ConflictPolicy::Skip) => {
+                report.posts.push(ImportedEntity {
+                    source: post.id.0,
+                    id: Some(id.0),
+                    name: post.title.clone(),
+                    action: ImportAction::Skipped,
+                });
+                continue;
+            }
+            (Some(id), ConflictPolicy::Overwrite) => {
+                history::record::<Post>(manager, id.0, "import")?;
+                manager.bind(id).update(
+                    UpdatePost::default()
+                        .title(post.title.clone())
+                        .updated(post.updated)
+                        .published(post.published),
+                )?;
+                (id, ImportAction::Overwritten)
+            }
+            (existing, _) => {
+                let (id, ..) = manager.import_post(
+                    UnsyncPost::<()>::new(
+                        PlatformId(0),
+                        pending_source("post"),
+                        post.title.clone(),
+                        vec![],
+                    )
+                    .published(post.published)
+                    .updated(post.updated),
+                    false,
+                )?;
+                // sources are unique, a duplicate goes in without one
+                let source = match existing {
+                    Some(_) => None,
+                    None => post.source.clone(),
+                };
+                manager
+                    .bind(id)
+                    .update(UpdatePost::default().source(source))?;
+                (id, ImportAction::Created)
+            }
+        };
+
+        let bound = manager.bind(id);
+        bound.remove_authors(&bound.list_authors()?)?;
+        bound.remove_tags(&bound.list_tags()?)?;
+        bound.remove_collections(&bound.list_collections()?)?;
+        bound.add_authors(&map_ids(&post.authors, &authors))?;
+        bound.add_tags(&map_ids(&post.tags, &tags))?;
+        bound.add_collections(&map_ids(&post.collections, &collections))?;
+
+        for old in &post.files {
+            let Some(file_meta) = file_metas.get(old) else {
+                continue;
+            };
+            // the name becomes a path inside the archive, it must stay in the post directory
+            let filename = sanitize_filename(&file_meta.filename).ok_or_else(|| {
+                ApiError::bad_request(format!("unsafe filename: {:?}", file_meta.filename))
+                    .with_field("bundle")
+            })?;
+            let new = manager.import_file_meta(
+                id,
+                &UnsyncFileMeta::new(filename.clone(), file_meta.mime.clone(), ()),
+            )?;
+            if let Some(temp) = staged.remove(old) {
+                moved.push(MovedFile {
+                    from: temp,
+                    to: Post::directory(id).join(filename),
+                });
+            }
+            files.insert(*old, new);
+        }
+
+        let content = post
+            .content
+            .iter()
+            .filter_map(|item| match item {
+                Content::File(file) => files.get(file).map(|file| Content::File(*file)),
+                item => Some(item.clone()),
+            })
+            .collect();
+        bound.update(
+            UpdatePost::default()
+                .content(content)
+                .comments(post.comments.clone())
+                .thumb(post.thumb.and_then(|thumb| files.get(&thumb).copied()))
+                .platform(platform_of(post.platform)),
+        )?;
+        search::index_post(manager, id)?;
+
+        report.posts.push(ImportedEntity {
+            source: post.id.0,
+            id: Some(id.0),
+            name: post.title.clone(),
+            action,
+        });
+    }
+
+    // thumbs of new authors and collections point at files of imported posts
+    for author in &manifest.authors {
+        if let (Some(thumb), Some(&id)) = (author.thumb, authors.get(&author.id))
+            && let Some(&thumb) = files.get(&thumb)
+            && is_created(&report.authors, author.id.0)
+        {
+            Binded::<AuthorId>::update(
+                &manager.bind(id),
+                UpdateAuthor::default().thumb(Some(thumb)),
+            )?;
+        }
+    }
+    for collection in &manifest.collections {
+        if let (Some(thumb), Some(&id)) = (collection.thumb, collections.get(&collection.id))
+            && let Some(&thumb) = files.get(&thumb)
+            && is_created(&report.collections, collection.id.0)
+        {
+            Binded::<CollectionId>::update(
+                &manager.bind(id),
+                UpdateCollection::default().thumb(Some(thumb)),
+            )?;
+        }
+    }
+
+    report.files = files.len();
+    Ok((report, moved))
+}
+
+fn map_ids<T: Copy + Eq + std::hash::Hash>(ids: &[T], mapping: &HashMap<T, T>) -> Vec<T> {
+    ids.iter()
+        .filter_map(|id| mapping.get(id).copied())
+        .collect()
+}
+
+fn is_created(entities: &[ImportedEntity], source: u32) -> bool {
+    entities
+        .iter()
+        .any(|entity| entity.source == source && entity.action == ImportAction::Created)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use post_archiver::{
+        AuthorId, FileMetaId, PlatformId, PostId,
+        importer::{UnsyncAlias, UnsyncAuthor, UnsyncFileMeta, UnsyncPost, UnsyncTag},
+    };
+    use serde_json::json;
+
+    use super::{
+        ConflictPolicy, ImportAction, ImportManifest, Staged, apply, move_file, read_manifest,
+    };
+    use crate::api::{export::export_to, testing::TestArchive};
+
+    fn manifest(title: &str, filename: &str) -> ImportManifest {
+        serde_json::from_value(json!({
+            "version": 1,
+            "posts": [{
+                "id": 7,
+                "title": title,
+                "content": ["hello", 3],
+                "source": "https://example.com/7",
+                "updated": "2024-01-02T00:00:00Z",
+                "published": "2024-01-01T00:00:00Z",
+                "thumb": null,
+                "platform": 2,
+                "tags": [5],
+                "files": [3],
+            }],
+            "platforms": [{ "id": 2, "name": "example" }],
+            "tags": [{ "id": 5, "name": "art", "platform": 2 }],
+            "file_metas": [{ "id": 3, "filename": filename, "mime": "image/png" }],
+        }))
+        .unwrap()
+    }
+
+    fn existing(archive: &TestArchive) -> PostId {
+        let post = UnsyncPost::<()>::new(
+            PlatformId(0),
+            "https://example.com/7".to_string(),
+            "existing".to_string(),
+            vec![],
+        );
+        archive.import_post(post, false).unwrap().0
+    }
+
+    fn titles(archive: &TestArchive) -> Vec<(String, Option<String>)> {
+        let mut stmt = archive
+            .conn()
+            .prepare("SELECT title, source FROM posts ORDER BY id")
+            .unwrap();
+        stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn creates_missing_entities() {
+        let archive = TestArchive::new();
+        let report = apply(
+            &archive,
+            &manifest("new", "a.png"),
+            ConflictPolicy::Skip,
+            Staged::new(),
+        )
+        .unwrap()
+        .0;
+
+        assert_eq!(report.posts[0].action, ImportAction::Created);
+        assert_eq!(report.platforms[0].action, ImportAction::Created);
+        assert_eq!(report.files, 1);
+        let post = archive
+            .get_post(PostId(report.posts[0].id.unwrap()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(post.source.as_deref(), Some("https://example.com/7"));
+        assert_eq!(post.platform.map(|id| id.0), report.platforms[0].id);
+    }
+
+    #[test]
+    fn skip_keeps_the_existing_post() {
+        let archive = TestArchive::new();
+        existing(&archive);
+        let report = apply(
+            &archive,
+            &manifest("new", "a.png"),
+            ConflictPolicy::Skip,
+            Staged::new(),
+        )
+        .unwrap()
+        .0;
+
+        assert_eq!(report.posts[0].action, ImportAction::Skipped);
+        assert_eq!(
+            titles(&archive),
+            vec![(
+                "existing".to_string(),
+                Some("https://example.com/7".to_string())
+            )]
+        );
+    }
+
+    #[test]
+    fn overwrite_replaces_the_existing_post() {
+        let archive = TestArchive::new();
+        let id = existing(&archive);
+        let report = apply(
+            &archive,
+            &manifest("new", "a.png"),
+            ConflictPolicy::Overwrite,
+            Staged::new(),
+        )
+        .unwrap()
+        .0;
+
+        assert_eq!(report.posts[0].action, ImportAction::Overwritten);
+        assert_eq!(report.posts[0].id, Some(id.0));
+        assert_eq!(
+            titles(&archive),
+            vec![("new".to_string(), Some("https://example.com/7".to_string()))]
+        );
+    }
+
+    #[test]
+    fn duplicate_drops_the_taken_source() {
+        let archive = TestArchive::new();
+        existing(&archive);
+        let report = apply(
+            &archive,
+            &manifest("new", "a.png"),
+            ConflictPolicy::Duplicate,
+            Staged::new(),
+        )
+        .unwrap()
+        .0;
+
+        assert_eq!(report.posts[0].action, ImportAction::Created);
+        assert_eq!(
+            titles(&archive),
+            vec![
+                (
+                    "existing".to_string(),
+                    Some("https://example.com/7".to_string())
+                ),
+                ("new".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn tags_match_by_name_across_platforms() {
+        let archive = TestArchive::new();
+        let tag = archive
+            .import_tag(UnsyncTag {
+                name: "art".to_string(),
+                platform: None,
+            })
+            .unwrap();
+        let report = apply(
+            &archive,
+            &manifest("new", "a.png"),
+            ConflictPolicy::Skip,
+            Staged::new(),
+        )
+        .unwrap()
+        .0;
+
+        assert_eq!(report.tags[0].action, ImportAction::Matched);
+        assert_eq!(report.tags[0].id, Some(tag.0));
+    }
+
+    #[test]
+    fn rejects_filenames_leaving_the_post() {
+        for filename in [
+            "../../escape.png",
+            "/etc/passwd",
+            "..",
+            "a/b.png",
+            "a\\b.png",
+        ] {
+            let archive = TestArchive::new();
+            let result = apply(
+                &archive,
+                &manifest("new", filename),
+                ConflictPolicy::Skip,
+                Staged::new(),
+            );
+            assert!(result.is_err(), "{filename} was accepted");
+        }
+    }
+
+    #[test]
+    fn moves_staged_files_once_committed() {
+        let archive = TestArchive::new();
+        let temp = archive.path.join("staged");
+        fs::write(&temp, b"png").unwrap();
+
+        let staged = Staged::from([(FileMetaId(3), temp.clone())]);
+        let (report, moved) = apply(
+            &archive,
+            &manifest("new", "a.png"),
+            ConflictPolicy::Skip,
+            staged,
+        )
+        .unwrap();
+
+        // nothing touches the archive directory before the commit
+        assert!(temp.exists());
+        let post = PostId(report.posts[0].id.unwrap());
+        let file = archive.find_file_meta(post, "a.png").unwrap().unwrap();
+        let path = archive
+            .path
+            .join(archive.get_file_meta(file).unwrap().unwrap().path());
+        assert!(!path.exists());
+
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            for file in &moved {
+                move_file(&file.from, &archive.path.join(&file.to)).await;
+            }
+        });
+        assert_eq!(fs::read(path).unwrap(), b"png");
+        assert!(!temp.exists());
+    }
+
+    #[test]
+    fn failed_imports_keep_the_existing_files() {
+        let archive = TestArchive::new();
+        let post = existing(&archive);
+        let file = archive
+            .import_file_meta(
+                post,
+                &UnsyncFileMeta::new("a.png".into(), "image/png".into(), ()),
+            )
+            .unwrap();
+        let path = archive
+            .path
+            .join(archive.get_file_meta(file).unwrap().unwrap().path());
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, b"old").unwrap();
+        let temp = archive.path.join("staged");
+        fs::write(&temp, b"new").unwrap();
+
+        // the second post fails after the first one overwrote a.png
+        let manifest: ImportManifest = serde_json::from_value(json!({
+            "version": 1,
+            "posts": [
+                { "id": 7, "title": "new", "source": "https://example.com/7",
+                  "updated": "2024-01-02T00:00:00Z", "published": "2024-01-01T00:00:00Z",
+                  "thumb": null, "platform": null, "files": [3] },
+                { "id": 8, "title": "bad", "source": null,
+                  "updated": "2024-01-02T00:00:00Z", "published": "2024-01-01T00:00:00Z",
+                  "thumb": null, "platform": null, "files": [4] },
+            ],
+            "file_metas": [
+                { "id": 3, "filename": "a.png", "mime": "image/png" },
+                { "id": 4, "filename": "../escape.png", "mime": "image/png" },
+            ],
+        }))
+        .unwrap();
+        let staged = Staged::from([(FileMetaId(3), temp.clone())]);
+        let result = apply(&archive, &manifest, ConflictPolicy::Overwrite, staged);
+
+        assert!(result.is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"old");
+        assert_eq!(fs::read(&temp).unwrap(), b"new");
+    }
+
+    #[test]
+    fn aliases_keep_platforms_no_post_uses() {
+        let source = TestArchive::new();
+        let posts = source.import_platform("example".to_string()).unwrap();
+        let other = source.import_platform("elsewhere".to_string()).unwrap();
+        let author = source
+            .import_author(UnsyncAuthor::new("someone".to_string()).aliases(vec![
+                UnsyncAlias::new(other, "someone-elsewhere".to_string()),
+            ]))
+            .unwrap();
+        let post = source
+            .import_post(
+                UnsyncPost::<()>::new(
+                    posts,
+                    "https://example.com/7".to_string(),
+                    "post".to_string(),
+                    vec![],
+                ),
+                false,
+            )
+            .unwrap()
+            .0;
+        source.bind(post).add_authors(&[author]).unwrap();
+
+        let bundle = source.path.join("bundle.tar");
+        export_to(&source, &[post], &bundle).unwrap();
+        let manifest = read_manifest(&bundle).unwrap();
+
+        let target = TestArchive::new();
+        let (report, _) = apply(&target, &manifest, ConflictPolicy::Skip, Staged::new()).unwrap();
+
+        let author = AuthorId(report.authors[0].id.unwrap());
+        let aliases = target.bind(author).list_aliases().unwrap();
+        assert_eq!(aliases.len(), 1);
+        assert_eq!(aliases[0].source, "someone-elsewhere");
+        let platform = target.get_platform(aliases[0].platform).unwrap().unwrap();
+        assert_eq!(platform.name, "elsewhere");
+    }
+}
@@ -0,0 +1,572 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use axum::{Router, extract::State, routing::get};
+use post_archiver::{
+    Author, AuthorId, Collection, CollectionId, Content, FileMeta, FileMetaId, PlatformId, Post,
+    PostId, Tag, TagId,
+    manager::{PostArchiverManager, UpdatePost},
+};
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use ts_rs::TS;
+
+use super::{
+    AppState,
+    category::Category,
+    error::{ApiError, ApiResult},
+    export,
+    extract::Json,
+    filter::{PostFilter, PostSortKey},
+    history,
+    import::{self, ImportOptions},
+    site,
+};
+use crate::config::{Command, Config};
+
+pub fn wrap_maintenance_route(router: Router<AppState>) -> Router<AppState> {
+    router
+        .route("/maintenance/check", get(check_handler).post(fix_handler))
+        .route("/maintenance/gc", get(gc_list_handler).post(gc_handler))
+}
+
+#[derive(Debug, Clone, Default, Serialize, TS)]
+#[ts(export)]
+pub struct CheckReport {
+    pub missing_files: Vec<MissingFile>,
+    pub untracked_files: Vec<String>,
+    pub dangling_content: Vec<DanglingContent>,
+    pub foreign_thumbs: Vec<ForeignThumb>,
+    pub orphan_tag_platforms: Vec<OrphanTagPlatform>,
+}
+
+impl CheckReport {
+    pub fn problems(&self) -> usize {
+        self.missing_files.len()
+            + self.untracked_files.len()
+            + self.dangling_content.len()
+            + self.foreign_thumbs.len()
+            + self.orphan_tag_platforms.len()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export)]
+pub struct MissingFile {
+    pub id: FileMetaId,
+    pub post: PostId,
+    pub path: String,
+}
+
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export)]
+pub struct DanglingContent {
+    pub post: PostId,
+    pub file: FileMetaId,
+}
+
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export)]
+pub struct ForeignThumb {
+    pub post: PostId,
+    pub thumb: FileMetaId,
+    // `None` when the file meta no longer exists
+    pub owner: Option<PostId>,
+}
+
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export)]
+pub struct OrphanTagPlatform {
+    pub tag: TagId,
+    pub platform: PlatformId,
+}
+
+async fn check_handler(State(state): State<AppState>) -> ApiResult<Json<CheckReport>> {
+    state.read(|manager| Ok(Json(check(manager)?))).await
+}
+
+async fn fix_handler(State(state): State<AppState>) -> ApiResult<Json<CheckReport>> {
+    state
+        .transaction(|manager| {
+            fix(manager)?;
+            Ok(Json(check(manager)?))
+        })
+        .await
+}
+
+pub fn check(manager: &PostArchiverManager) -> ApiResult<CheckReport> {
+    let mut report = CheckReport::default();
+
+    let mut known = HashSet::new();
+    for id in file_meta_ids(manager)? {
+        let Some(file_meta) = manager.get_file_meta(id)? else {
+            continue;
+        };
+        let path = file_meta.path();
+        if !manager.path.join(&path).is_file() {
+            report.missing_files.push(MissingFile {
+                id,
+                post: file_meta.post,
+                path: path.to_string_lossy().into_owned(),
+            });
+        }
+        known.insert(path);
+    }
+
+    let (files, _) = walk_archive(&manager.path)?;
+    report.untracked_files = files
+        .into_iter()
+        .filter(|path| !known.contains(path))
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect();
+
+    report.dangling_content = dangling_content(manager)?;
+    report.foreign_thumbs = foreign_thumbs(manager)?;
+    report.orphan_tag_platforms = orphan_tag_platforms(manager)?;
+    Ok(report)
+}
+
+// only repairs that lose nothing but broken references, untracked files are left alone
+pub fn fix(manager: &PostArchiverManager) -> ApiResult<()> {
+    let conn = manager.conn();
+
+    for id in file_meta_ids(manager)? {
+        let Some(file_meta) = manager.get_file_meta(id)? else {
+            continue;
+        };
+        if !manager.path.join(file_meta.path()).is_file() {
+            history::record::<FileMeta>(manager, id.0, "fsck")?;
+            conn.execute("DELETE FROM file_metas WHERE id = ?1", params![id.0])?;
+        }
+    }
+
+    let mut posts: Vec<PostId> = dangling_content(manager)?
+        .iter()
+        .map(|dangling| dangling.post)
+        .collect();
+    posts.dedup();
+    for id in posts {
+        let Some(post) = manager.get_post(id)? else {
+            continue;
+        };
+        history::record::<Post>(manager, id.0, "fsck")?;
+        let mut content = post.content;
+        content.retain(|item| match item {
+            Content::File(file) => manager.get_file_meta(*file).ok().flatten().is_some(),
+            _ => true,
+        });
+        manager
+            .bind(id)
+            .update(UpdatePost::default().content(content))?;
+    }
+
+    for thumb in foreign_thumbs(manager)? {
+        history::record::<Post>(manager, thumb.post.0, "fsck")?;
+        manager
+            .bind(thumb.post)
+            .update(UpdatePost::default().thumb(None))?;
+    }
+
+    for orphan in orphan_tag_platforms(manager)? {
+        history::record::<Tag>(manager, orphan.tag.0, "fsck")?;
+        conn.execute(
+            "UPDATE tags SET platform = NULL WHERE id = ?1",
+            params![orphan.tag.0],
+        )?;
+    }
+
+    Ok(())
+}
+
+fn file_meta_ids(manager: &PostArchiverManager) -> rusqlite::Result<Vec<FileMetaId>> {
+    let mut stmt = manager
+        .conn()
+        .prepare("SELECT id FROM file_metas ORDER BY id")?;
+    stmt.query_map([], |row| row.get(0).map(FileMetaId))?
+        .collect()
+}
+
+fn dangling_content(manager: &PostArchiverManager) -> ApiResult<Vec<DanglingContent>> {
+    let mut stmt = manager.conn().prepare("SELECT id FROM posts ORDER BY id")?;
+    let ids = stmt
+        .query_map([], |row| row.get(0).map(PostId))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let mut dangling = vec![];
+    for id in ids {
+        let Some(post) = manager.get_post(id)? else {
+            continue;
+        };
+        for item in &post.content {
+            if let Content::File(file) = item
+                && manager.get_file_meta(*file)?.is_none()
+            {
+                dangling.push(DanglingContent {
+                    post: id,
+                    file: *file,
+                });
+            }
+        }
+    }
+    Ok(dangling)
+}
+
+fn foreign_thumbs(manager: &PostArchiverManager) -> rusqlite::Result<Vec<ForeignThumb>> {
+    let mut stmt = manager.conn().prepare(
+        "SELECT p.id, p.thumb, f.post FROM posts p LEFT JOIN file_metas f ON f.id = p.thumb
+        WHERE p.thumb IS NOT NULL AND (f.id IS NULL OR f.post != p.id) ORDER BY p.id",
+    )?;
+    stmt.query_map([], |row| {
+        Ok(ForeignThumb {
+            post: PostId(row.get(0)?),
+            thumb: FileMetaId(row.get(1)?),
+            owner: row.get::<_, Option<u32>>(2)?.map(PostId),
+        })
+    })?
+    .collect()
+}
+
+fn orphan_tag_platforms(manager: &PostArchiverManager) -> rusqlite::Result<Vec<OrphanTagPlatform>> {
+    let mut stmt = manager.conn().prepare(
+        "SELECT id, platform FROM tags
+        WHERE platform IS NOT NULL AND platform NOT IN (SELECT id FROM platforms) ORDER BY id",
+    )?;
+    stmt.query_map([], |row| {
+        Ok(OrphanTagPlatform {
+            tag: TagId(row.get(0)?),
+            platform: PlatformId(row.get(1)?),
+        })
+    })?
+    .collect()
+}
+
+// every file and directory below the archive's subdirectories, relative to the
+// root; the databases at the root and hidden editor directories are not archive content
+pub fn walk_archive(root: &Path) -> std::io::Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+    let mut files = vec![];
+    let mut dirs = vec![];
+    let mut pending = vec![];
+    for entry in fs::read_dir(root)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() && !is_hidden(&entry.path()) {
+            pending.push(entry.path());
+        }
+    }
+
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if is_hidden(&path) {
+                continue;
+            }
+            if path.is_dir() {
+                pending.push(path);
+            } else if let Ok(relative) = path.strip_prefix(root) {
+                files.push(relative.to_path_buf());
+            }
+        }
+        if let Ok(relative) = dir.strip_prefix(root) {
+            dirs.push(relative.to_path_buf());
+        }
+    }
+
+    files.sort();
+    dirs.sort();
+    Ok((files, dirs))
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, TS)]
+#[ts(export)]
+pub struct GcReport {
+    #[serde(default)]
+    pub orphan_files: Vec<String>,
+    #[serde(default)]
+    pub empty_dirs: Vec<String>,
+    #[serde(default)]
+    pub authors: Vec<AuthorId>,
+    #[serde(default)]
+    pub tags: Vec<TagId>,
+    #[serde(default)]
+    pub collections: Vec<CollectionId>,
+    #[serde(default)]
+    pub bytes: u64,
+}
+
+impl GcReport {
+    // keep only what is also listed in `confirmed`
+    fn retain(&mut self, confirmed: &GcReport) {
+        self.orphan_files
+            .retain(|path| confirmed.orphan_files.contains(path));
+        self.empty_dirs
+            .retain(|path| confirmed.empty_dirs.contains(path));
+        self.authors.retain(|id| confirmed.authors.contains(id));
+        self.tags.retain(|id| confirmed.tags.contains(id));
+        self.collections
+            .retain(|id| confirmed.collections.contains(id));
+    }
+}
+
+async fn gc_list_handler(State(state): State<AppState>) -> ApiResult<Json<GcReport>> {
+    state.read(|manager| Ok(Json(gc_list(manager)?))).await
+}
+
+// removes what the confirmed listing and a fresh scan agree on
+async fn gc_handler(
+    State(state): State<AppState>,
+    Json(confirmed): Json<GcReport>,
+) -> ApiResult<Json<GcReport>> {
+    state
+        .transaction(move |manager| {
+            let mut report = gc_list(manager)?;
+            report.retain(&confirmed);
+            gc(manager, &mut report)?;
+            Ok(Json(report))
+        })
+        .await
+}
+
+pub fn gc_list(manager: &PostArchiverManager) -> ApiResult<GcReport> {
+    let mut tracked = HashSet::new();
+    for id in file_meta_ids(manager)? {
+        if let Some(file_meta) = manager.get_file_meta(id)? {
+            tracked.insert(file_meta.path());
+        }
+    }
+    let kept: HashSet<&Path> = tracked.iter().flat_map(|path| path.ancestors()).collect();
+
+    let (files, dirs) = walk_archive(&manager.path)?;
+    let orphan_files: Vec<PathBuf> = files
+        .into_iter()
+        .filter(|path| !tracked.contains(path))
+        .collect();
+    let bytes = orphan_files
+        .iter()
+        .filter_map(|path| fs::metadata(manager.path.join(path)).ok())
+        .map(|metadata| metadata.len())
+        .sum();
+
+    Ok(GcReport {
+        orphan_files: orphan_files.iter().map(|path| display(path)).collect(),
+        // directories holding nothing but orphan files end up empty as well
+        empty_dirs: dirs
+            .iter()
+            .filter(|dir| !kept.contains(dir.as_path()))
+            .map(|dir| display(dir))
+            .collect(),
+        authors: unused(manager, "authors", "author_posts", "author")?
+            .into_iter()
+            .map(AuthorId)
+            .collect(),
+        tags: unused(manager, "tags", "post_tags", "tag")?
+            .into_iter()
+            .map(TagId)
+            .collect(),
+        collections: unused(manager, "collections", "collection_posts", "collection")?
+            .into_iter()
+            .map(CollectionId)
+            .collect(),
+        bytes,
+    })
+}
+
+// deletes the entities inside the caller's transaction, entries that fail on
+// disk are dropped from the report
+pub fn gc(manager: &PostArchiverManager, report: &mut GcReport) -> ApiResult<()> {
+    for &id in &report.authors {
+        history::record::<Author>(manager, id.0, "gc")?;
+        Author::delete_entity(manager, id)?;
+    }
+    for &id in &report.tags {
+        history::record::<Tag>(manager, id.0, "gc")?;
+        Tag::delete_entity(manager, id)?;
+    }
+    for &id in &report.collections {
+        history::record::<Collection>(manager, id.0, "gc")?;
+        Collection::delete_entity(manager, id)?;
+    }
+
+    let root = manager.path.clone();
+    let mut bytes = 0;
+    report.orphan_files.retain(|path| {
+        let path = root.join(path);
+        let size = fs::metadata(&path).map(|metadata| metadata.len());
+        match fs::remove_file(&path) {
+            Ok(()) => {
+                bytes += size.unwrap_or_default();
+                true
+            }
+            Err(err) => {
+                warn!("failed to remove {}: {err}", path.display());
+                false
+            }
+        }
+    });
+    report.bytes = bytes;
+
+    // deepest first so parents are empty by the time they are removed
+    report
+        .empty_dirs
+        .sort_by_key(|dir| std::cmp::Reverse(dir.len()));
+    report.empty_dirs.retain(|dir| {
+        let path = root.join(dir);
+        match fs::remove_dir(&path) {
+            Ok(()) => true,
+            Err(err) => {
+                warn!("failed to remove {}: {err}", path.display());
+                false
+            }
+        }
+    });
+    report.empty_dirs.sort();
+
+    Ok(())
+}
+
+fn unused(
+    manager: &PostArchiverManager,
+    table: &str,
+    links: &str,
+    column: &str,
+) -> rusqlite::Result<Vec<u32>> {
+    let mut stmt = manager.conn().prepare(&format!(
+        "SELECT id FROM {table} WHERE id NOT IN (SELECT {column} FROM {links}) ORDER BY id"
+    ))?;
+    stmt.query_map([], |row| row.get(0))?.collect()
+}
+
+fn display(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+}
+
+pub async fn run(config: &Config, command: Command) -> ApiResult<()> {
+    match command {
+        Command::Check { fix: apply } => {
+            let state = AppState::new(config.path.clone(), 1, !apply)?;
+            let report = if apply {
+                state
+                    .transaction(|manager| {
+                        fix(manager)?;
+                        check(manager)
+                    })
+                    .await?
+            } else {
+                state.read(check).await?
+            };
+
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&report).map_err(ApiError::internal)?
+            );
+            info!("{} problem(s) found", report.problems());
+            Ok(())
+        }
+        Command::Gc { apply } => {
+            let state = AppState::new(config.path.clone(), 1, !apply)?;
+            let report = if apply {
+                state
+                    .transaction(|manager| {
+                        let mut report = gc_list(manager)?;
+                        gc(manager, &mut report)?;
+                        Ok(report)
+                    })
+                    .await?
+            } else {
+                state.read(gc_list).await?
+            };
+
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&report).map_err(ApiError::internal)?
+            );
+            if !apply {
+                info!("dry run, pass --apply to remove the listed entries");
+            }
+            Ok(())
+        }
+        Command::Export {
+            output,
+            posts,
+            author,
+            tag,
+            collection,
+            platform,
+        } => {
+            let filter = PostFilter {
+                author: author.into_iter().map(AuthorId).collect(),
+                tag: tag.into_iter().map(TagId).collect(),
+                collection: collection.into_iter().map(CollectionId).collect(),
+                platform: platform.into_iter().map(PlatformId).collect(),
+                ..Default::default()
+            };
+
+            let state = AppState::new(config.path.clone(), 1, true)?;
+            let count = state
+                .read(move |manager| {
+                    let ids = if posts.is_empty() {
+                        filter.select(manager, None)?
+                    } else {
+                        posts.into_iter().map(PostId).collect()
+                    };
+                    export::export_to(manager, &ids, &output)?;
+                    Ok(ids.len())
+                })
+                .await?;
+            info!("{count} post(s) exported");
+            Ok(())
+        }
+        Command::Site {
+            output,
+            per_page,
+            author,
+            tag,
+            collection,
+            platform,
+        } => {
+            let filter = PostFilter {
+                author: author.into_iter().map(AuthorId).collect(),
+                tag: tag.into_iter().map(TagId).collect(),
+                collection: collection.into_iter().map(CollectionId).collect(),
+                platform: platform.into_iter().map(PlatformId).collect(),
+                sort: Some(PostSortKey::Published),
+                ..Default::default()
+            };
+
+            let state = AppState::new(config.path.clone(), 1, true)?;
+            let report = state
+                .read(move |manager| site::generate(manager, &filter, &output, per_page))
+                .await?;
+            info!(
+                "{} post(s) on {} page(s) with {} file(s) written",
+                report.posts, report.pages, report.files
+            );
+            Ok(())
+        }
+        Command::Import {
+            bundle,
+            policy,
+            preview,
+        } => {
+            // a preview writes inside a savepoint too, so it needs a writable connection
+            let state = AppState::new(config.path.clone(), 1, false)?;
+            let report = import::import(&state, bundle, ImportOptions { policy, preview }).await?;
+
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&report).map_err(ApiError::internal)?
+            );
+            if preview {
+                info!("preview only, run without --preview to import");
+            }
+            Ok(())
+        }
+    }
+}
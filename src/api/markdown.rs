@@ -0,0 +1,500 @@
+use std::collections::{HashMap, HashSet};
+
+use axum::{
+    Router,
+    extract::State,
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use chrono::{DateTime, Utc};
+use post_archiver::{
+    AuthorId, CollectionId, Content, FileMetaId, Post, PostId, TagId, manager::PostArchiverManager,
+};
+use rusqlite::{OptionalExtension, Params, params};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use super::{
+    AppState,
+    category::{Category, UpdateCategoryPayload, post::UpdatePostPayload},
+    error::{ApiError, ApiResult},
+    extract::Path,
+    history,
+    post::PostResponse,
+    relation::WithRelations,
+    version::{etag_of, if_match, matches, precondition_failed, with_etag},
+};
+
+// front matter values are written as JSON, which is also valid YAML, so the
+// file opens cleanly in any markdown editor and parses back without a YAML crate
+
+const FENCE: &str = "---";
+// sits between two text blocks, which may contain blank lines of their own
+const BLOCK_BREAK: &str = "<!-- block -->";
+// link target of a file written by id, plain `#` anchors stay links
+const FILE_ID: &str = "#file:";
+
+pub fn wrap_markdown_route(router: Router<AppState>) -> Router<AppState> {
+    router.route(
+        "/posts/{id}/markdown",
+        get(get_markdown_handler).put(put_markdown_handler),
+    )
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FrontMatter {
+    title: Option<String>,
+    published: Option<DateTime<Utc>>,
+    updated: Option<DateTime<Utc>>,
+    authors: Option<Vec<String>>,
+    tags: Option<Vec<String>>,
+    collections: Option<Vec<String>>,
+}
+
+async fn get_markdown_handler(
+    Path(id): Path<PostId>,
+    State(state): State<AppState>,
+) -> ApiResult<Response> {
+    let (etag, markdown) = state
+        .read(move |manager| {
+            let post = PostResponse::load(manager, id)?
+                .ok_or_else(|| ApiError::not_found(Post::ROUTE, id.0))?;
+            let etag = etag_of(&post)?;
+            Ok((etag, to_markdown(&WithRelations::new(manager, post)?)))
+        })
+        .await?;
+
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                "text/markdown; charset=utf-8".to_string(),
+            ),
+            (header::ETAG, etag),
+        ],
+        markdown,
+    )
+        .into_response())
+}
+
+async fn put_markdown_handler(
+    Path(id): Path<PostId>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    markdown: String,
+) -> ApiResult<Response> {
+    let expected = if_match(&headers);
+    state
+        .transaction(move |manager| {
+            let current = Post::current_version(manager, id)?
+                .ok_or_else(|| ApiError::not_found(Post::ROUTE, id.0))?;
+            if let Some(expected) = expected
+                && !matches(&expected, &current.etag)
+            {
+                return Ok(precondition_failed(current));
+            }
+
+            let payload = from_markdown(manager, id, &markdown)?;
+            history::record::<Post>(manager, id.0, "update")?;
+            payload.apply(manager, id)?;
+
+            let etag = Post::current_version(manager, id)?.map(|current| current.etag);
+            Ok(with_etag(StatusCode::NO_CONTENT, etag))
+        })
+        .await
+}
+
+pub fn to_markdown(post: &WithRelations<PostResponse>) -> String {
+    let names = |names: Vec<&str>| json(&names);
+    let platform = post
+        .platforms
+        .iter()
+        .find(|platform| Some(platform.id) == post.inner.platform)
+        .map(|platform| platform.name.as_str());
+
+    let mut markdown = format!("{FENCE}\n");
+    for (key, value) in [
+        ("title", json(&post.inner.title)),
+        ("source", json(&post.inner.source)),
+        ("published", json(&post.inner.published)),
+        ("updated", json(&post.inner.updated)),
+        ("platform", json(&platform)),
+        (
+            "authors",
+            names(post.authors.iter().map(|a| a.name.as_str()).collect()),
+        ),
+        (
+            "tags",
+            names(post.tags.iter().map(|t| t.name.as_str()).collect()),
+        ),
+        (
+            "collections",
+            names(post.collections.iter().map(|c| c.name.as_str()).collect()),
+        ),
+    ] {
+        markdown.push_str(&format!("{key}: {value}\n"));
+    }
+    markdown.push_str(FENCE);
+    markdown.push('\n');
+
+    let file_metas: HashMap<FileMetaId, _> = post.file_metas.iter().map(|f| (f.id, f)).collect();
+    let mut previous: Option<&Content> = None;
+    for block in &post.inner.content {
+        if let (Some(Content::Text(_)), Content::Text(_)) = (previous, block) {
+            markdown.push_str(&format!("\n{BLOCK_BREAK}\n"));
+        }
+        previous = Some(block);
+        markdown.push('\n');
+        match block {
+            Content::Text(text) => markdown.push_str(text),
+            Content::File(id) => match file_metas.get(id) {
+                Some(file_meta) => {
+                    let image = if file_meta.mime.starts_with("image/") {
+                        "!"
+                    } else {
+                        ""
+                    };
+                    markdown.push_str(&format!(
+                        "{image}[{}](<{}>)",
+                        file_meta.filename.replace(']', "\\]"),
+                        file_path(&file_meta.path())
+                    ));
+                }
+                // keeps the reference so a round trip does not drop it
+                None => markdown.push_str(&format!("[Missing file](<{FILE_ID}{}>)", id.0)),
+            },
+        }
+        markdown.push('\n');
+    }
+    markdown
+}
+
+fn json<T: serde::Serialize>(value: &T) -> String {
+    serde_json::to_string(value).unwrap_or_else(|_| "null".to_string())
+}
+
+fn file_path(path: &std::path::Path) -> String {
+    path.iter()
+        .map(|segment| segment.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+pub fn from_markdown(
+    manager: &PostArchiverManager,
+    id: PostId,
+    markdown: &str,
+) -> ApiResult<UpdatePostPayload> {
+    let markdown = markdown.replace("\r\n", "\n");
+    let (mut front, body) = split_front_matter(&markdown)?;
+
+    let source = front.remove("source");
+    let platform = match front.remove("platform") {
+        Some(Value::String(name)) => Some(Value::from(
+            find_named(
+                manager,
+                "SELECT id FROM platforms WHERE name = ?1",
+                params![name],
+            )?
+            .ok_or_else(|| {
+                ApiError::bad_request(format!("unknown platform {name:?}")).with_field("platform")
+            })?,
+        )),
+        Some(Value::Null) => Some(Value::Null),
+        Some(_) => {
+            return Err(
+                ApiError::bad_request("platform must be a name or null").with_field("platform")
+            );
+        }
+        None => None,
+    };
+    let front: FrontMatter = serde_json::from_value(Value::Object(front))
+        .map_err(|err| ApiError::bad_request(format!("invalid front matter: {err}")))?;
+
+    let authors = front
+        .authors
+        .map(|names| {
+            resolve("authors", &names, |name| {
+                find_named(
+                    manager,
+                    "SELECT id FROM authors WHERE name = ?1",
+                    params![name],
+                )
+            })
+            .map(|ids| ids.into_iter().map(AuthorId).collect())
+        })
+        .transpose()?;
+    let collections = front
+        .collections
+        .map(|names| {
+            resolve("collections", &names, |name| {
+                find_named(
+                    manager,
+                    "SELECT id FROM collections WHERE name = ?1",
+                    params![name],
+                )
+            })
+            .map(|ids| ids.into_iter().map(CollectionId).collect())
+        })
+        .transpose()?;
+    // tag names repeat across platforms, prefer the one of this post
+    let tags = front
+        .tags
+        .map(|names| {
+            resolve("tags", &names, |name| {
+                find_named(
+                    manager,
+                    "SELECT id FROM tags WHERE name = ?1
+                    ORDER BY platform IS NOT (SELECT platform FROM posts WHERE id = ?2), id",
+                    params![name, id.0],
+                )
+            })
+            .map(|ids| ids.into_iter().map(TagId).collect())
+        })
+        .transpose()?;
+
+    Ok(UpdatePostPayload {
+        title: front.title,
+        source,
+        content: Some(parse_content(manager, id, body)?),
+        thumb: None,
+        comments: None,
+        updated: front.updated,
+        published: front.published,
+        platform,
+        authors,
+        collections,
+        tags,
+    })
+}
+
+fn split_front_matter(markdown: &str) -> ApiResult<(Map<String, Value>, &str)> {
+    let Some(rest) = markdown.strip_prefix(&format!("{FENCE}\n")) else {
+        return Ok((Map::new(), markdown));
+    };
+    let (front, body) = match rest.split_once(&format!("\n{FENCE}\n")) {
+        Some(split) => split,
+        None => rest
+            .strip_suffix(&format!("\n{FENCE}"))
+            .map(|front| (front, ""))
+            .ok_or_else(|| ApiError::bad_request("front matter is never closed"))?,
+    };
+
+    let mut map = Map::new();
+    for line in front.lines().filter(|line| !line.trim().is_empty()) {
+        let (key, value) = line
+            .split_once(':')
+            .ok_or_else(|| ApiError::bad_request(format!("invalid front matter line {line:?}")))?;
+        let key = key.trim();
+        let value = serde_json::from_str(value.trim()).map_err(|err| {
+            ApiError::bad_request(format!("invalid front matter value: {err}")).with_field(key)
+        })?;
+        map.insert(key.to_string(), value);
+    }
+    Ok((map, body))
+}
+
+// a line that references a file of the post is a block of its own, the text
+// around it is one block unless split by `BLOCK_BREAK`, blank lines included
+fn parse_content(manager: &PostArchiverManager, id: PostId, body: &str) -> ApiResult<Vec<Content>> {
+    let file_metas = manager.bind(id).list_file_metas()?;
+    let files: HashMap<String, FileMetaId> = file_metas
+        .iter()
+        .filter_map(|&file| manager.get_file_meta(file).ok().flatten())
+        .map(|file_meta| (file_path(&file_meta.path()), file_meta.id))
+        .collect();
+    // `#file:id` may name a file of the post or keep a reference the content already has
+    let mut known: HashSet<FileMetaId> = file_metas.into_iter().collect();
+    if let Some(post) = manager.get_post(id)? {
+        known.extend(post.content.iter().filter_map(|block| match block {
+            Content::File(file) => Some(*file),
+            Content::Text(_) => None,
+        }));
+    }
+
+    let mut content = vec![];
+    let mut text: Vec<&str> = vec![];
+    for line in body.split('\n') {
+        if line.trim() == BLOCK_BREAK {
+            push_text(&mut content, &mut text);
+            continue;
+        }
+
+        let file = match file_reference(line) {
+            Some(target) => match target.strip_prefix(FILE_ID) {
+                Some(file) => {
+                    let file = file
+                        .parse()
+                        .ok()
+                        .map(FileMetaId)
+                        .filter(|file| known.contains(file));
+                    Some(file.ok_or_else(|| {
+                        ApiError::bad_request(format!("unknown file {target:?}"))
+                            .with_field("content")
+                    })?)
+                }
+                None => files.get(target).copied(),
+            },
+            None => None,
+        };
+        match file {
+            Some(file) => {
+                push_text(&mut content, &mut text);
+                content.push(Content::File(file));
+            }
+            // links to anything outside the post stay plain text
+            None => text.push(line),
+        }
+    }
+    push_text(&mut content, &mut text);
+    Ok(content)
+}
+
+// every block is written after a blank line and ends with a newline, which is
+// taken off again here
+fn push_text(content: &mut Vec<Content>, lines: &mut Vec<&str>) {
+    let text = lines.join("\n");
+    lines.clear();
+    let text = text.strip_prefix('\n').unwrap_or(&text);
+    let text = text.strip_suffix('\n').unwrap_or(text);
+    if !text.trim().is_empty() {
+        content.push(Content::Text(text.to_string()));
+    }
+}
+
+// the target of a block that is nothing but `[name](target)` or `![name](target)`
+fn file_reference(block: &str) -> Option<&str> {
+    let block = block.trim();
+    let block = block.strip_prefix('!').unwrap_or(block);
+    let rest = block.strip_prefix('[')?.strip_suffix(')')?;
+    let (_, target) = rest.rsplit_once("](")?;
+    let target = target
+        .strip_prefix('<')
+        .and_then(|target| target.strip_suffix('>'))
+        .unwrap_or(target);
+    Some(target)
+}
+
+fn find_named(
+    manager: &PostArchiverManager,
+    sql: &str,
+    params: impl Params,
+) -> ApiResult<Option<u32>> {
+    Ok(manager
+        .conn()
+        .query_row(sql, params, |row| row.get(0))
+        .optional()?)
+}
+
+fn resolve(
+    field: &str,
+    names: &[String],
+    find: impl Fn(&str) -> ApiResult<Option<u32>>,
+) -> ApiResult<Vec<u32>> {
+    let mut ids = vec![];
+    let mut unknown = vec![];
+    for name in names {
+        match find(name)? {
+            Some(id) if !ids.contains(&id) => ids.push(id),
+            Some(_) => {}
+            None => unknown.push(name.as_str()),
+        }
+    }
+    if !unknown.is_empty() {
+        return Err(
+            ApiError::bad_request(format!("unknown {field}: {}", unknown.join(", ")))
+                .with_field(field),
+        );
+    }
+    Ok(ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use post_archiver::{
+        Content, FileMetaId, PlatformId, PostId,
+        importer::{UnsyncFileMeta, UnsyncPost, UnsyncTag},
+        manager::UpdatePost,
+    };
+    use serde_json::to_value;
+
+    use super::{from_markdown, to_markdown};
+    use crate::api::{post::PostResponse, relation::WithRelations, testing::TestArchive};
+
+    fn post(archive: &TestArchive, source: &str) -> PostId {
+        let post = UnsyncPost::<()>::new(
+            PlatformId(0),
+            source.to_string(),
+            source.to_string(),
+            vec![],
+        );
+        archive.import_post(post, false).unwrap().0
+    }
+
+    fn file(archive: &TestArchive, post: PostId, name: &str) -> FileMetaId {
+        let file_meta = UnsyncFileMeta::new(name.to_string(), "image/png".to_string(), ());
+        archive.import_file_meta(post, &file_meta).unwrap()
+    }
+
+    fn markdown(archive: &TestArchive, id: PostId) -> String {
+        let post = PostResponse::load(archive, id).unwrap().unwrap();
+        to_markdown(&WithRelations::new(archive, post).unwrap())
+    }
+
+    #[test]
+    fn content_survives_a_round_trip() {
+        let archive = TestArchive::new();
+        let id = post(&archive, "post");
+        let image = file(&archive, id, "a b.png");
+        let elsewhere = file(&archive, post(&archive, "other"), "other.png");
+        let tag = archive
+            .import_tag(UnsyncTag {
+                name: "art".to_string(),
+                platform: Some(PlatformId(0)),
+            })
+            .unwrap();
+        archive.bind(id).add_tags(&[tag]).unwrap();
+
+        let content = vec![
+            Content::Text("first paragraph\n\nsecond paragraph".to_string()),
+            Content::Text("next block\n".to_string()),
+            Content::File(image),
+            Content::File(elsewhere),
+            Content::Text("[a link](https://example.com)".to_string()),
+            Content::Text("[top](#intro)".to_string()),
+            Content::Text(format!("[see](#{})", image.0)),
+        ];
+        archive
+            .bind(id)
+            .update(UpdatePost::default().content(content.clone()))
+            .unwrap();
+
+        let payload = from_markdown(&archive, id, &markdown(&archive, id)).unwrap();
+        assert_eq!(
+            to_value(payload.content).unwrap(),
+            to_value(Some(content)).unwrap()
+        );
+        assert_eq!(payload.title.as_deref(), Some("post"));
+        assert_eq!(payload.tags, Some(vec![tag]));
+    }
+
+    #[test]
+    fn file_ids_must_belong_to_the_post() {
+        let archive = TestArchive::new();
+        let id = post(&archive, "post");
+        let own = file(&archive, id, "own.png");
+        let foreign = file(&archive, post(&archive, "other"), "other.png");
+
+        let payload = from_markdown(&archive, id, &format!("![own](<#file:{}>)\n", own.0)).unwrap();
+        assert_eq!(
+            to_value(payload.content).unwrap(),
+            to_value(Some(vec![Content::File(own)])).unwrap()
+        );
+
+        let err = from_markdown(&archive, id, &format!("![other](<#file:{}>)\n", foreign.0))
+            .expect_err("a file of another post was accepted");
+        assert_eq!(err.field.as_deref(), Some("content"));
+    }
+}
@@ -1,38 +1,66 @@
+pub mod bulk;
 pub mod category;
+pub mod error;
+pub mod export;
+pub mod extract;
+pub mod feed;
 pub mod file;
+pub mod filter;
+pub mod history;
+pub mod import;
+pub mod maintenance;
+pub mod markdown;
 pub mod post;
 pub mod relation;
+pub mod search;
+pub mod site;
+pub mod snapshot;
+pub mod state;
+pub mod tar;
+#[cfg(test)]
+mod testing;
 pub mod utils;
+pub mod version;
 
-use std::sync::{Arc, Mutex};
-
-use crate::config::Config;
-use axum::{Router, http::StatusCode};
+use crate::{
+    auth::{Auth, get_auth_router, require_auth},
+    config::Config,
+};
+use axum::{
+    Router,
+    extract::{Request, State},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+};
 use category::Category;
-use post_archiver::{Author, Collection, Platform, Post, Tag, manager::PostArchiverManager};
-
-#[derive(Clone)]
-pub struct AppState {
-    manager: Arc<Mutex<PostArchiverManager>>,
-}
+use error::{ApiError, ApiErrorCode};
+use extract::Json;
+use post_archiver::{Author, Collection, Platform, Post, Tag};
+use serde::Serialize;
+use ts_rs::TS;
 
-impl AppState {
-    pub fn manager(&self) -> std::sync::MutexGuard<'_, PostArchiverManager> {
-        self.manager.lock().unwrap()
-    }
-}
+pub use state::AppState;
 
-pub fn get_api_router(config: &Config) -> Router<()> {
+pub fn get_api_router(config: &Config, auth: &Auth) -> Router<()> {
     let path = config.path.clone();
 
-    let manager = PostArchiverManager::open(path).unwrap().unwrap();
-    let manager = Arc::new(Mutex::new(manager));
-
-    let state = AppState { manager };
+    let public_url =
+        (config.public_url.clone()).unwrap_or_else(|| format!("http://localhost:{}", config.port));
+    let state = AppState::new(path, config.readers, config.read_only)
+        .unwrap()
+        .with_public_url(&public_url);
 
     let router = Router::new();
 
     let router = file::wrap_file_route(router);
+    let router = bulk::wrap_bulk_route(router);
+    let router = maintenance::wrap_maintenance_route(router);
+    let router = snapshot::wrap_snapshot_route(router);
+    let router = export::wrap_export_route(router);
+    let router = import::wrap_import_route(router);
+    let router = markdown::wrap_markdown_route(router);
+    let router = feed::wrap_feed_route(router);
 
     let router = Post::wrap_category_route(router);
     let router = Tag::wrap_category_route(router);
@@ -40,5 +68,46 @@ pub fn get_api_router(config: &Config) -> Router<()> {
     let router = Platform::wrap_category_route(router);
     let router = Collection::wrap_category_route(router);
 
-    router.fallback(StatusCode::NOT_FOUND).with_state(state)
+    let router = router
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            reject_in_read_only,
+        ))
+        .route_layer(middleware::from_fn_with_state(auth.clone(), require_auth))
+        .route("/mode", get(mode_handler))
+        .merge(get_auth_router(auth.clone()));
+
+    router.fallback(not_found_handler).with_state(state)
+}
+
+async fn not_found_handler() -> ApiError {
+    ApiErrorCode::NotFound.into()
+}
+
+async fn reject_in_read_only(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if state.read_only() && !request.method().is_safe() {
+        ApiError::new(
+            ApiErrorCode::ReadOnly,
+            "the editor is running in read-only mode",
+        )
+        .into_response()
+    } else {
+        next.run(request).await
+    }
+}
+
+#[derive(Debug, Serialize, TS)]
+#[ts(export)]
+pub struct ModeResponse {
+    pub read_only: bool,
+}
+
+async fn mode_handler(State(state): State<AppState>) -> Json<ModeResponse> {
+    Json(ModeResponse {
+        read_only: state.read_only(),
+    })
 }
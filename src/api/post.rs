@@ -1,23 +1,40 @@
+use std::{
+    collections::HashMap,
+    path::{Path as FsPath, PathBuf},
+};
+
 use axum::{
-    Json,
-    extract::{Path, State},
+    extract::State,
     http::StatusCode,
+    response::{IntoResponse, Response},
 };
-use axum_extra::extract::Query;
 use chrono::{DateTime, Utc};
 use post_archiver::{
-    AuthorId, CollectionId, Comment, Content, FileMetaId, PlatformId, Post, PostId, TagId,
-    impl_from_query,
-    query::{
-        Countable, Paginate, Query as QueryTrait, SortDir, Sortable, Totalled, post::PostSort,
-    },
+    AuthorId, CollectionId, Comment, Content, FileMeta, FileMetaId, PlatformId, Post, PostId,
+    TagId, impl_from_query, manager::PostArchiverManager, query::Totalled,
 };
+use rusqlite::{OptionalExtension, params};
 use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tracing::error;
 use ts_rs::TS;
 
-use crate::api::{AppState, utils::Pagination};
+use crate::api::{
+    AppState,
+    category::Category,
+    error::{ApiError, ApiErrorCode, ApiResult},
+    extract::{Json, Path, Query},
+    file::{list_post_filenames, resolve_collision},
+    filter::PostFilter,
+    history,
+    utils::Pagination,
+    version::{Versioned, etag_of},
+};
 
-use super::relation::{RequireRelations, WithRelations};
+use super::{
+    relation::{RequireRelations, WithRelations},
+    search,
+};
 
 #[derive(Debug, Clone, Serialize, TS)]
 #[ts(export)]
@@ -62,38 +79,19 @@ impl RequireRelations for PostResponse {
     }
 }
 
-pub async fn get_post_handler(
-    Path(id): Path<PostId>,
-    State(state): State<AppState>,
-) -> Result<Json<WithRelations<PostResponse>>, StatusCode> {
-    let manager = state.manager();
-
-    let post = manager
-        .get_post(id)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .ok_or(StatusCode::NOT_FOUND)?;
-
-    let tags = manager
-        .bind(id)
-        .list_tags()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    let authors = manager
-        .bind(id)
-        .list_authors()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-    let collections = manager
-        .bind(id)
-        .list_collections()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    let file_metas = manager
-        .bind(post.id)
-        .list_file_metas()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-
-    WithRelations::new(
-        &manager,
-        PostResponse {
+impl PostResponse {
+    pub fn load(manager: &PostArchiverManager, id: PostId) -> ApiResult<Option<Self>> {
+        let Some(post) = manager.get_post(id)? else {
+            return Ok(None);
+        };
+
+        let tags = manager.bind(id).list_tags()?;
+        let authors = manager.bind(id).list_authors()?;
+        let collections = manager.bind(id).list_collections()?;
+
+        let file_metas = manager.bind(post.id).list_file_metas()?;
+
+        Ok(Some(PostResponse {
             id: post.id,
             title: post.title,
             content: post.content,
@@ -107,10 +105,24 @@ pub async fn get_post_handler(
             authors,
             collections,
             file_metas,
-        },
-    )
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
-    .map(Json::from)
+        }))
+    }
+}
+
+pub async fn get_post_handler(
+    Path(id): Path<PostId>,
+    State(state): State<AppState>,
+) -> ApiResult<Versioned<WithRelations<PostResponse>>> {
+    state
+        .read(move |manager| {
+            let post = PostResponse::load(manager, id)?
+                .ok_or_else(|| ApiError::not_found(Post::ROUTE, id.0))?;
+            Ok(Versioned {
+                etag: etag_of(&post)?,
+                body: WithRelations::new(manager, post)?,
+            })
+        })
+        .await
 }
 
 #[derive(Debug, Clone, Serialize, TS)]
@@ -140,57 +152,433 @@ impl RequireRelations for PostShortResponse {
     }
 }
 
-#[derive(Debug, Deserialize)]
-pub struct PostFilter {
-    #[serde(default)]
-    pub search: String,
-    #[serde(default)]
-    pub author: Option<AuthorId>,
-    #[serde(default)]
-    pub tag: Option<TagId>,
-    #[serde(default)]
-    pub collection: Option<CollectionId>,
-    #[serde(default)]
-    pub platform: Option<PlatformId>,
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export)]
+pub struct PostListResponse {
+    pub items: Vec<PostShortResponse>,
+    pub total: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub snippets: Option<HashMap<PostId, String>>,
+}
+
+impl RequireRelations for PostListResponse {
+    fn platforms(&self) -> Vec<PlatformId> {
+        self.items.platforms()
+    }
+    fn file_metas(&self) -> Vec<FileMetaId> {
+        self.items.file_metas()
+    }
 }
 
 pub async fn list_post_handler(
     Query(filter): Query<PostFilter>,
     Query(pagination): Query<Pagination>,
     State(state): State<AppState>,
-) -> Result<Json<WithRelations<Totalled<Vec<PostShortResponse>>>>, StatusCode> {
-    let manager = state.manager();
+) -> ApiResult<Json<WithRelations<PostListResponse>>> {
+    state
+        .read(move |manager| {
+            let Totalled { items, total } = filter.query_page(manager, &pagination)?;
 
-    let mut query = manager.posts();
+            let snippets = filter
+                .search_query()
+                .map(|q| {
+                    let ids: Vec<PostId> = items.iter().map(|post| post.id).collect();
+                    search::snippets(manager, q, &ids)
+                })
+                .transpose()?;
+
+            Ok(Json(WithRelations::new(
+                manager,
+                PostListResponse {
+                    items,
+                    total,
+                    snippets,
+                },
+            )?))
+        })
+        .await
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct DeletePostOptions {
+    #[serde(default)]
+    pub cascade: bool,
+    #[serde(default)]
+    pub dry_run: bool,
+}
 
-    if !filter.search.is_empty() {
-        query.title.contains(&filter.search);
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export)]
+pub struct RemovedFile {
+    pub id: FileMetaId,
+    pub post: PostId,
+    pub path: String,
+    pub size: u64,
+    // where the file is kept so reverting the deletion brings it back, unset in dry runs
+    #[serde(skip)]
+    #[ts(skip)]
+    pub trash: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Serialize, TS)]
+#[ts(export)]
+pub struct DeleteReport {
+    pub dry_run: bool,
+    pub files: Vec<RemovedFile>,
+    pub bytes_freed: u64,
+}
+
+// a file handed to another post or brought in by an import, moved on disk once the
+// transaction is committed
+#[derive(Debug, Clone)]
+pub struct MovedFile {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+#[derive(Debug, Default)]
+pub struct DeletedPost {
+    pub files: Vec<RemovedFile>,
+    pub moved: Vec<MovedFile>,
+}
+
+impl DeletedPost {
+    pub fn extend(&mut self, other: DeletedPost) {
+        self.files.extend(other.files);
+        self.moved.extend(other.moved);
+    }
+
+    // moves go first, so a file handed to a post deleted later on is trashed from there
+    pub async fn apply_to_disk(&self, root: &FsPath) {
+        for file in &self.moved {
+            move_file(&root.join(&file.from), &root.join(&file.to)).await;
+        }
+        for file in &self.files {
+            if let Some(trash) = &file.trash {
+                move_file(&root.join(&file.path), trash).await;
+            }
+        }
+    }
+}
+
+impl DeleteReport {
+    pub fn new(dry_run: bool, files: Vec<RemovedFile>) -> Self {
+        Self {
+            dry_run,
+            bytes_freed: files.iter().map(|file| file.size).sum(),
+            files,
+        }
+    }
+}
+
+pub async fn delete_post_handler(
+    Path(id): Path<PostId>,
+    Query(options): Query<DeletePostOptions>,
+    State(state): State<AppState>,
+) -> ApiResult<Response> {
+    let DeletePostOptions { cascade, dry_run } = options;
+
+    if dry_run {
+        let files = state
+            .read(move |manager| {
+                manager
+                    .get_post(id)?
+                    .ok_or_else(|| ApiError::not_found(Post::ROUTE, id.0))?;
+                shared_files(manager, id)?;
+                if cascade {
+                    unused_files(manager, id)
+                } else {
+                    Ok(vec![])
+                }
+            })
+            .await?;
+        return Ok(Json(DeleteReport::new(true, files)).into_response());
+    }
+
+    let deleted = state
+        .transaction(move |manager| {
+            manager
+                .get_post(id)?
+                .ok_or_else(|| ApiError::not_found(Post::ROUTE, id.0))?;
+            delete_post(manager, id, cascade)
+        })
+        .await?;
+    deleted.apply_to_disk(state.path()).await;
+
+    if !cascade {
+        return Ok(StatusCode::NO_CONTENT.into_response());
+    }
+    Ok(Json(DeleteReport::new(false, deleted.files)).into_response())
+}
+
+// the caller applies the returned file changes to disk once the transaction is committed
+pub fn delete_post(
+    manager: &PostArchiverManager,
+    id: PostId,
+    cascade: bool,
+) -> ApiResult<DeletedPost> {
+    let revision = history::record::<Post>(manager, id.0, "delete")?;
+
+    // the rows of the post's files go with it, so files other posts still show
+    // are handed over to the first of them
+    let mut moved = vec![];
+    for (file_meta, owner) in shared_files(manager, id)? {
+        let taken = list_post_filenames(manager, owner)?;
+        let filename = resolve_collision(file_meta.filename.clone(), &taken);
+        manager.conn().execute(
+            "UPDATE file_metas SET post = ?1, filename = ?2 WHERE id = ?3",
+            params![owner.0, filename, file_meta.id.0],
+        )?;
+        let to = FileMeta {
+            post: owner,
+            filename,
+            ..file_meta.clone()
+        };
+        moved.push(MovedFile {
+            from: file_meta.path(),
+            to: to.path(),
+        });
+    }
+
+    let mut files = if cascade {
+        unused_files(manager, id)?
+    } else {
+        vec![]
+    };
+    for file in &mut files {
+        file.trash = Some(trash_path(&manager.path, revision, file.id));
+        manager
+            .conn()
+            .execute("DELETE FROM file_metas WHERE id = ?1", params![file.id.0])?;
+    }
+
+    Post::delete_entity(manager, id)?;
+    Ok(DeletedPost { files, moved })
+}
+
+// files of the post that another post shows, with the first post showing each, a
+// file only an author or collection still uses as its thumb has nowhere to go
+fn shared_files(manager: &PostArchiverManager, id: PostId) -> ApiResult<Vec<(FileMeta, PostId)>> {
+    let conn = manager.conn();
+    let mut owner = conn.prepare_cached(
+        "SELECT id FROM posts WHERE id != ?1 AND (
+            thumb = ?2 OR EXISTS (
+                SELECT 1 FROM json_each(posts.content) AS content
+                WHERE content.type = 'integer' AND content.value = ?2
+            )
+        )
+        ORDER BY id LIMIT 1",
+    )?;
+    let mut thumb = conn.prepare_cached(
+        "SELECT EXISTS (SELECT 1 FROM authors WHERE thumb = ?1)
+        OR EXISTS (SELECT 1 FROM collections WHERE thumb = ?1)",
+    )?;
+
+    let mut files = vec![];
+    for file in manager.bind(id).list_file_metas()? {
+        let Some(file_meta) = manager.get_file_meta(file)? else {
+            continue;
+        };
+        match owner
+            .query_row(params![id.0, file.0], |row| row.get(0).map(PostId))
+            .optional()?
+        {
+            Some(owner) => files.push((file_meta, owner)),
+            None if thumb.query_row(params![file.0], |row| row.get(0))? => {
+                return Err(ApiError::new(
+                    ApiErrorCode::Constraint,
+                    format!(
+                        "file {} is the thumb of an author or collection, change it first",
+                        file.0
+                    ),
+                )
+                .with_id(file.0));
+            }
+            None => {}
+        }
     }
+    Ok(files)
+}
+
+// files of the post that no other post, author or collection shows as its thumb
+// or in its content
+fn unused_files(manager: &PostArchiverManager, id: PostId) -> ApiResult<Vec<RemovedFile>> {
+    let mut stmt = manager.conn().prepare_cached(
+        "SELECT id FROM file_metas WHERE post = ?1
+        AND id NOT IN (SELECT thumb FROM posts WHERE thumb IS NOT NULL AND id != ?1)
+        AND id NOT IN (
+            SELECT content.value FROM posts, json_each(posts.content) AS content
+            WHERE posts.id != ?1 AND content.type = 'integer'
+        )
+        AND id NOT IN (SELECT thumb FROM authors WHERE thumb IS NOT NULL)
+        AND id NOT IN (SELECT thumb FROM collections WHERE thumb IS NOT NULL)
+        ORDER BY id",
+    )?;
+    let ids = stmt
+        .query_map(params![id.0], |row| row.get(0).map(FileMetaId))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
 
-    if let Some(author) = filter.author {
-        query.authors.insert(author);
+    let mut files = Vec::with_capacity(ids.len());
+    for file_id in ids {
+        let Some(file_meta) = manager.get_file_meta(file_id)? else {
+            continue;
+        };
+        let path = file_meta.path();
+        let size = std::fs::metadata(manager.path.join(&path))
+            .map(|metadata| metadata.len())
+            .unwrap_or_default();
+        files.push(RemovedFile {
+            id: file_id,
+            post: id,
+            path: path.to_string_lossy().into_owned(),
+            size,
+            trash: None,
+        });
     }
+    Ok(files)
+}
 
-    if let Some(tag) = filter.tag {
-        query.tags.insert(tag);
+// files of a deleted post are kept under the revision that recorded the deletion
+pub fn trash_path(root: &FsPath, revision: i64, file: FileMetaId) -> PathBuf {
+    history::trash_path(root, revision).join(file.0.to_string())
+}
+
+pub(super) async fn move_file(from: &FsPath, to: &FsPath) {
+    let moved = async {
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::rename(from, to).await
+    };
+    if let Err(err) = moved.await {
+        error!(
+            "failed to move {} to {}: {err}",
+            from.display(),
+            to.display()
+        );
+        return;
+    }
+    // drop the post directory once its last file is gone
+    if let Some(parent) = from.parent() {
+        fs::remove_dir(parent).await.ok();
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use post_archiver::{
+        Content, FileMetaId, PlatformId, Post, PostId,
+        importer::{UnsyncAuthor, UnsyncFileMeta, UnsyncPost},
+        manager::{UpdateAuthor, UpdatePost},
+    };
+
+    use super::{delete_post, unused_files};
+    use crate::api::{error::ApiErrorCode, history, testing::TestArchive};
 
-    if let Some(collection) = filter.collection {
-        query.collections.insert(collection);
+    fn post(archive: &TestArchive, source: &str) -> PostId {
+        let post = UnsyncPost::<()>::new(
+            PlatformId(0),
+            source.to_string(),
+            source.to_string(),
+            vec![],
+        );
+        archive.import_post(post, false).unwrap().0
     }
 
-    if let Some(platform) = filter.platform {
-        query.platforms.insert(platform);
+    fn file(archive: &TestArchive, post: PostId, name: &str) -> FileMetaId {
+        let file_meta = UnsyncFileMeta::new(name.to_string(), "image/png".to_string(), ());
+        let id = archive.import_file_meta(post, &file_meta).unwrap();
+        let path = archive
+            .path
+            .join(archive.get_file_meta(id).unwrap().unwrap().path());
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, name).unwrap();
+        id
     }
 
-    let result = query
-        .sort(PostSort::Id, SortDir::Desc)
-        .pagination(pagination.limit(), pagination.page())
-        .with_total()
-        .query::<PostShortResponse>()
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    #[test]
+    fn files_shown_by_other_posts_are_kept() {
+        let archive = TestArchive::new();
+        let (deleted, other) = (post(&archive, "deleted"), post(&archive, "other"));
+        let unused = file(&archive, deleted, "unused.png");
+        let shown = file(&archive, deleted, "shown.png");
+        let thumb = file(&archive, deleted, "thumb.png");
+        // the name is taken in the post the file is handed to
+        file(&archive, other, "shown.png");
+        archive
+            .bind(other)
+            .update(
+                UpdatePost::default()
+                    .content(vec![Content::Text("see".to_string()), Content::File(shown)])
+                    .thumb(Some(thumb)),
+            )
+            .unwrap();
 
-    WithRelations::new(&manager, result)
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
-        .map(Json::from)
+        let files = unused_files(&archive, deleted).unwrap();
+        assert_eq!(
+            files.iter().map(|file| file.id).collect::<Vec<_>>(),
+            vec![unused]
+        );
+
+        let removed = delete_post(&archive, deleted, true).unwrap();
+        tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(removed.apply_to_disk(&archive.path));
+
+        assert!(archive.get_file_meta(unused).unwrap().is_none());
+        assert_eq!(archive.get_post(other).unwrap().unwrap().thumb, Some(thumb));
+        for (file, filename) in [(shown, "shown (1).png"), (thumb, "thumb.png")] {
+            let file_meta = archive.get_file_meta(file).unwrap().unwrap();
+            assert_eq!(
+                (file_meta.post, file_meta.filename.as_str()),
+                (other, filename)
+            );
+            let path = archive.path.join(file_meta.path());
+            assert!(path.exists(), "{} was not moved", path.display());
+        }
+    }
+
+    #[test]
+    fn author_thumbs_block_the_delete() {
+        let archive = TestArchive::new();
+        let post = post(&archive, "post");
+        let thumb = file(&archive, post, "avatar.png");
+        let author = archive
+            .import_author(UnsyncAuthor::new("jack".to_string()))
+            .unwrap();
+        archive
+            .bind(author)
+            .update(UpdateAuthor::default().thumb(Some(thumb)))
+            .unwrap();
+
+        let err = delete_post(&archive, post, false).unwrap_err();
+        assert_eq!(err.code, ApiErrorCode::Constraint);
+    }
+
+    #[test]
+    fn reverting_a_cascading_delete_restores_files() {
+        let archive = TestArchive::new();
+        let post = post(&archive, "post");
+        let file = file(&archive, post, "a.png");
+        let path = archive
+            .path
+            .join(archive.get_file_meta(file).unwrap().unwrap().path());
+
+        let deleted = delete_post(&archive, post, true).unwrap();
+        tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(deleted.apply_to_disk(&archive.path));
+        assert!(!path.exists());
+        assert!(deleted.files[0].trash.as_ref().unwrap().exists());
+
+        let revision = archive
+            .conn()
+            .query_row("SELECT MAX(id) FROM editor.revisions", [], |row| row.get(0))
+            .unwrap();
+        history::revert::<Post>(&archive, post.0, revision).unwrap();
+
+        assert_eq!(archive.bind(post).list_file_metas().unwrap(), vec![file]);
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "a.png");
+    }
 }
@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+
+use post_archiver::{Comment, Content, PostId, manager::PostArchiverManager};
+use rusqlite::{Connection, ErrorCode, params};
+use serde_json::Value;
+
+use super::{
+    error::{ApiError, ApiErrorCode, ApiResult},
+    state::has_editor_db,
+};
+
+pub fn init(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE VIRTUAL TABLE IF NOT EXISTS editor.post_search USING fts5 (
+            title, content, comments, tokenize = 'unicode61 remove_diacritics 2'
+        );
+        CREATE TABLE IF NOT EXISTS editor.post_search_state (
+            post INTEGER PRIMARY KEY,
+            updated NOT NULL
+        );",
+    )
+}
+
+// bring the index up to date with posts changed outside of the editor
+pub fn sync(manager: &PostArchiverManager) -> ApiResult<()> {
+    let conn = manager.conn();
+    let tx = conn.unchecked_transaction()?;
+
+    conn.execute_batch(
+        "DELETE FROM editor.post_search WHERE rowid NOT IN (SELECT id FROM posts);
+        DELETE FROM editor.post_search_state WHERE post NOT IN (SELECT id FROM posts);",
+    )?;
+
+    let stale: Vec<u32> = conn
+        .prepare(
+            "SELECT p.id FROM posts p
+            LEFT JOIN editor.post_search_state s ON s.post = p.id
+            WHERE s.updated IS NOT p.updated",
+        )?
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    for id in stale {
+        index_post(manager, PostId(id))?;
+    }
+
+    tx.commit()?;
+    Ok(())
+}
+
+pub fn index_post(manager: &PostArchiverManager, id: PostId) -> ApiResult<()> {
+    let conn = manager.conn();
+    remove_post(conn, id)?;
+
+    let Some(post) = manager.get_post(id)? else {
+        return Ok(());
+    };
+    conn.execute(
+        "INSERT INTO editor.post_search (rowid, title, content, comments) VALUES (?1, ?2, ?3, ?4)",
+        params![
+            id.0,
+            post.title,
+            content_text(&post.content),
+            comments_text(&post.comments)
+        ],
+    )?;
+    conn.execute(
+        "INSERT INTO editor.post_search_state (post, updated)
+        SELECT id, updated FROM posts WHERE id = ?1",
+        params![id.0],
+    )?;
+    Ok(())
+}
+
+pub fn remove_post(conn: &Connection, id: PostId) -> rusqlite::Result<()> {
+    conn.execute(
+        "DELETE FROM editor.post_search WHERE rowid = ?1",
+        params![id.0],
+    )?;
+    conn.execute(
+        "DELETE FROM editor.post_search_state WHERE post = ?1",
+        params![id.0],
+    )?;
+    Ok(())
+}
+
+// a read-only editor never creates the index, so there is nothing to search in yet
+pub fn ensure_index(conn: &Connection) -> ApiResult<()> {
+    let exists = has_editor_db(conn)?
+        && conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM editor.sqlite_master WHERE name = 'post_search')",
+            [],
+            |row| row.get(0),
+        )?;
+    if exists {
+        Ok(())
+    } else {
+        Err(ApiError::new(
+            ApiErrorCode::ReadOnly,
+            "full-text search is unavailable until the editor has run once without --read-only",
+        )
+        .with_field("q"))
+    }
+}
+
+pub fn snippets(
+    manager: &PostArchiverManager,
+    q: &str,
+    ids: &[PostId],
+) -> ApiResult<HashMap<PostId, String>> {
+    if ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let ids = Value::from(ids.iter().map(|id| id.0).collect::<Vec<_>>()).to_string();
+    let mut stmt = manager.conn().prepare(
+        "SELECT rowid, snippet(post_search, -1, '<mark>', '</mark>', '…', 24)
+        FROM editor.post_search
+        WHERE post_search MATCH ?1 AND rowid IN (SELECT value FROM json_each(?2))",
+    )?;
+    stmt.query_map(params![q, ids], |row| {
+        Ok((PostId(row.get(0)?), row.get(1)?))
+    })
+    .and_then(|rows| rows.collect())
+    .map_err(|err| query_error(err, q))
+}
+
+pub fn query_error(err: rusqlite::Error, q: &str) -> ApiError {
+    match &err {
+        rusqlite::Error::SqliteFailure(failure, _) if failure.code == ErrorCode::Unknown => {
+            ApiError::new(
+                ApiErrorCode::BadRequest,
+                format!("invalid search query {q:?}"),
+            )
+            .with_field("q")
+        }
+        _ => err.into(),
+    }
+}
+
+fn content_text(content: &[Content]) -> String {
+    content
+        .iter()
+        .filter_map(|content| match content {
+            Content::Text(text) => Some(text.as_str()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn comments_text(comments: &[Comment]) -> String {
+    let mut text = String::new();
+    let mut stack: Vec<&Comment> = comments.iter().collect();
+    while let Some(comment) = stack.pop() {
+        text.push_str(&comment.text);
+        text.push('\n');
+        stack.extend(&comment.replies);
+    }
+    text
+}
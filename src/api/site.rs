@@ -0,0 +1,487 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path as FsPath, PathBuf},
+};
+
+use post_archiver::{
+    Author, AuthorId, Collection, CollectionId, Comment, Content, FileMeta, FileMetaId, Tag, TagId,
+    manager::PostArchiverManager,
+};
+use serde::Serialize;
+use tracing::warn;
+
+use super::{
+    category::Category, error::ApiResult, filter::PostFilter, post::PostResponse,
+    relation::WithRelations, utils::Pagination,
+};
+
+// pages live at most one directory deep, so links only ever need "" or "../"
+// as prefix and the site can be opened straight from disk
+
+const FILES_DIR: &str = "files";
+const STYLE: &str = "style.css";
+
+#[derive(Debug, Default, Serialize)]
+pub struct SiteReport {
+    pub posts: usize,
+    pub pages: usize,
+    pub files: usize,
+}
+
+struct Site<'a> {
+    manager: &'a PostArchiverManager,
+    output: PathBuf,
+    per_page: u64,
+    // every file a written page points at, copied once all pages are done
+    files: HashMap<FileMetaId, FileMeta>,
+    report: SiteReport,
+}
+
+pub fn generate(
+    manager: &PostArchiverManager,
+    filter: &PostFilter,
+    output: &FsPath,
+    per_page: u64,
+) -> ApiResult<SiteReport> {
+    let mut site = Site {
+        manager,
+        output: output.to_path_buf(),
+        per_page: per_page.max(1),
+        files: HashMap::new(),
+        report: SiteReport::default(),
+    };
+    fs::create_dir_all(output)?;
+    fs::write(output.join(STYLE), STYLESHEET)?;
+
+    let mut authors = BTreeMap::new();
+    let mut tags = BTreeMap::new();
+    let mut collections = BTreeMap::new();
+    for id in filter.select(manager, None)? {
+        let Some(post) = PostResponse::load(manager, id)? else {
+            continue;
+        };
+        let post = WithRelations::new(manager, post)?;
+        authors.extend(post.authors.iter().map(|a| (a.id.0, a.name.clone())));
+        tags.extend(post.tags.iter().map(|t| (t.id.0, t.name.clone())));
+        collections.extend(post.collections.iter().map(|c| (c.id.0, c.name.clone())));
+        site.post_page(&post)?;
+    }
+
+    site.list_pages(filter, "", "index", "Posts")?;
+    site.category_pages(filter, Author::ROUTE, "Authors", &authors, |filter, id| {
+        filter.author.push(AuthorId(id))
+    })?;
+    site.category_pages(filter, Tag::ROUTE, "Tags", &tags, |filter, id| {
+        filter.tag.push(TagId(id))
+    })?;
+    site.category_pages(
+        filter,
+        Collection::ROUTE,
+        "Collections",
+        &collections,
+        |filter, id| filter.collection.push(CollectionId(id)),
+    )?;
+
+    site.copy_files();
+    Ok(site.report)
+}
+
+impl Site<'_> {
+    fn write_page(&mut self, path: &str, html: String) -> ApiResult<()> {
+        let path = self.output.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, html)?;
+        self.report.pages += 1;
+        Ok(())
+    }
+
+    fn file_url(&mut self, root: &str, file_meta: &FileMeta) -> String {
+        self.files.insert(file_meta.id, file_meta.clone());
+        format!("{root}{FILES_DIR}/{}", url_path(&file_meta.path()))
+    }
+
+    fn post_page(&mut self, post: &WithRelations<PostResponse>) -> ApiResult<()> {
+        const ROOT: &str = "../";
+
+        let mut html = format!("<article>\n<h1>{}</h1>\n", escape(&post.inner.title));
+        html.push_str(&format!(
+            "<p class=\"meta\"><time datetime=\"{}\">{}</time>",
+            post.inner.published.to_rfc3339(),
+            post.inner.published.format("%Y-%m-%d %H:%M"),
+        ));
+        let platform = post.inner.platform;
+        if let Some(platform) = post.platforms.iter().find(|p| Some(p.id) == platform) {
+            html.push_str(&format!(" · {}", escape(&platform.name)));
+        }
+        if let Some(source) = &post.inner.source {
+            html.push_str(&format!(" · <a href=\"{}\">source</a>", escape(source)));
+        }
+        html.push_str("</p>\n");
+
+        let links = [
+            (
+                Author::ROUTE,
+                post.authors
+                    .iter()
+                    .map(|a| (a.id.0, a.name.as_str()))
+                    .collect::<Vec<_>>(),
+            ),
+            (
+                Tag::ROUTE,
+                post.tags
+                    .iter()
+                    .map(|t| (t.id.0, t.name.as_str()))
+                    .collect(),
+            ),
+            (
+                Collection::ROUTE,
+                post.collections
+                    .iter()
+                    .map(|c| (c.id.0, c.name.as_str()))
+                    .collect(),
+            ),
+        ];
+        for (route, entries) in links {
+            if entries.is_empty() {
+                continue;
+            }
+            html.push_str(&format!("<p class=\"{route}\">"));
+            for (id, name) in entries {
+                html.push_str(&format!(
+                    "<a href=\"{ROOT}{route}/{id}.html\">{}</a> ",
+                    escape(name)
+                ));
+            }
+            html.push_str("</p>\n");
+        }
+
+        // text blocks are markdown source, kept as written rather than rendered, and
+        // file blocks may show a file another post holds
+        for block in &post.inner.content {
+            match block {
+                Content::Text(text) => {
+                    html.push_str(&format!("<p class=\"text\">{}</p>\n", escape(text)))
+                }
+                Content::File(id) => match self.manager.get_file_meta(*id)? {
+                    Some(file_meta) => {
+                        let url = self.file_url(ROOT, &file_meta);
+                        html.push_str(&file_html(&url, &file_meta));
+                    }
+                    None => html.push_str("<p class=\"missing\">[Missing file]</p>\n"),
+                },
+            }
+        }
+
+        if !post.inner.comments.is_empty() {
+            html.push_str("<h2>Comments</h2>\n");
+            comments_html(&mut html, &post.inner.comments);
+        }
+        html.push_str("</article>\n");
+
+        let id = post.inner.id.0;
+        self.write_page(
+            &format!("posts/{id}.html"),
+            layout(ROOT, &post.inner.title, &html),
+        )?;
+        self.report.posts += 1;
+        Ok(())
+    }
+
+    // writes `{dir}/{stem}.html`, `{dir}/{stem}-2.html`, ... and returns the post count
+    fn list_pages(
+        &mut self,
+        filter: &PostFilter,
+        dir: &str,
+        stem: &str,
+        title: &str,
+    ) -> ApiResult<u64> {
+        let root = if dir.is_empty() { "" } else { "../" };
+        let page_name = |page: u64| match page {
+            0 => format!("{stem}.html"),
+            page => format!("{stem}-{}.html", page + 1),
+        };
+
+        let mut page = 0;
+        loop {
+            let pagination = Pagination {
+                limit: Some(self.per_page),
+                page: Some(page),
+            };
+            let list =
+                WithRelations::new(self.manager, filter.query_page(self.manager, &pagination)?)?;
+            let pages = list.inner.total.div_ceil(self.per_page).max(1);
+
+            let thumbs: HashMap<FileMetaId, &FileMeta> =
+                list.file_metas.iter().map(|f| (f.id, f)).collect();
+            let mut html = format!("<h1>{}</h1>\n<ul class=\"posts\">\n", escape(title));
+            for post in &list.inner.items {
+                html.push_str("<li>");
+                if let Some(thumb) = post.thumb.and_then(|id| thumbs.get(&id)) {
+                    let url = self.file_url(root, thumb);
+                    html.push_str(&format!("<img src=\"{url}\" alt=\"\" loading=\"lazy\">"));
+                }
+                html.push_str(&format!(
+                    "<a href=\"{root}posts/{}.html\">{}</a></li>\n",
+                    post.id.0,
+                    escape(&post.title)
+                ));
+            }
+            html.push_str("</ul>\n");
+
+            if pages > 1 {
+                html.push_str("<nav class=\"pages\">");
+                if page > 0 {
+                    html.push_str(&format!(
+                        "<a href=\"{}\">Previous</a> ",
+                        page_name(page - 1)
+                    ));
+                }
+                html.push_str(&format!("Page {} of {pages}", page + 1));
+                if page + 1 < pages {
+                    html.push_str(&format!(" <a href=\"{}\">Next</a>", page_name(page + 1)));
+                }
+                html.push_str("</nav>\n");
+            }
+
+            let path = match dir {
+                "" => page_name(page),
+                dir => format!("{dir}/{}", page_name(page)),
+            };
+            self.write_page(&path, layout(root, title, &html))?;
+
+            page += 1;
+            if page >= pages {
+                return Ok(list.inner.total);
+            }
+        }
+    }
+
+    fn category_pages(
+        &mut self,
+        filter: &PostFilter,
+        route: &str,
+        title: &str,
+        entries: &BTreeMap<u32, String>,
+        narrow: impl Fn(&mut PostFilter, u32),
+    ) -> ApiResult<()> {
+        let mut html = format!("<h1>{title}</h1>\n<ul class=\"index\">\n");
+        for (&id, name) in entries {
+            let mut filter = filter.clone();
+            narrow(&mut filter, id);
+            let count = self.list_pages(&filter, route, &id.to_string(), name)?;
+            html.push_str(&format!(
+                "<li><a href=\"{id}.html\">{}</a> <span class=\"count\">{count}</span></li>\n",
+                escape(name)
+            ));
+        }
+        html.push_str("</ul>\n");
+        self.write_page(&format!("{route}/index.html"), layout("../", title, &html))
+    }
+
+    fn copy_files(&mut self) {
+        for file_meta in self.files.values() {
+            let path = file_meta.path();
+            let source = self.manager.path.join(&path);
+            let target = self.output.join(FILES_DIR).join(&path);
+            let copied = target
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|()| fs::copy(&source, &target));
+            match copied {
+                Ok(_) => self.report.files += 1,
+                Err(err) => warn!("skipping {} in site: {err}", source.display()),
+            }
+        }
+    }
+}
+
+fn layout(root: &str, title: &str, body: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{title}</title>
+<link rel="stylesheet" href="{root}{STYLE}">
+</head>
+<body>
+<header><nav><a href="{root}index.html">Posts</a> <a href="{root}authors/index.html">Authors</a> <a href="{root}tags/index.html">Tags</a> <a href="{root}collections/index.html">Collections</a></nav></header>
+<main>
+{body}</main>
+</body>
+</html>
+"#,
+        title = escape(title),
+    )
+}
+
+fn file_html(url: &str, file_meta: &FileMeta) -> String {
+    let filename = escape(&file_meta.filename);
+    if file_meta.mime.starts_with("image/") {
+        format!("<figure><img src=\"{url}\" alt=\"{filename}\" loading=\"lazy\"></figure>\n")
+    } else if file_meta.mime.starts_with("video/") {
+        format!("<figure><video src=\"{url}\" controls preload=\"metadata\"></video></figure>\n")
+    } else {
+        format!("<p class=\"file\"><a href=\"{url}\">{filename}</a></p>\n")
+    }
+}
+
+fn comments_html(html: &mut String, comments: &[Comment]) {
+    html.push_str("<ul class=\"comments\">\n");
+    for comment in comments {
+        html.push_str(&format!(
+            "<li><b>{}</b><p class=\"text\">{}</p>",
+            escape(&comment.user),
+            escape(&comment.text)
+        ));
+        if !comment.replies.is_empty() {
+            comments_html(html, &comment.replies);
+        }
+        html.push_str("</li>\n");
+    }
+    html.push_str("</ul>\n");
+}
+
+pub(super) fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// percent-encodes every path segment, archive filenames keep whatever the source used
+pub(super) fn url_path(path: &FsPath) -> String {
+    path.iter()
+        .map(|segment| {
+            segment
+                .to_string_lossy()
+                .bytes()
+                .map(|byte| match byte {
+                    b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                        (byte as char).to_string()
+                    }
+                    byte => format!("%{byte:02X}"),
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+const STYLESHEET: &str = r#"body { margin: 0; font-family: system-ui, sans-serif; line-height: 1.5; color: #222; background: #fafaf9; }
+header { padding: 0.75rem 1rem; background: #292524; }
+header a { color: #fafaf9; margin-right: 1rem; text-decoration: none; }
+main { max-width: 48rem; margin: 0 auto; padding: 1rem; }
+a { color: #b45309; }
+.meta, .count { color: #78716c; }
+.text { white-space: pre-wrap; }
+figure { margin: 1rem 0; }
+figure img, figure video { max-width: 100%; }
+ul.posts { list-style: none; padding: 0; }
+ul.posts li { display: flex; align-items: center; gap: 0.75rem; margin: 0.5rem 0; }
+ul.posts img { width: 4rem; height: 4rem; object-fit: cover; }
+ul.comments { padding-left: 1rem; border-left: 2px solid #e7e5e4; }
+.pages { margin-top: 1rem; }
+"#;
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use post_archiver::{
+        Content, FileMetaId, PlatformId, Post, PostId,
+        importer::{UnsyncAuthor, UnsyncFileMeta, UnsyncPost},
+        manager::UpdatePost,
+    };
+
+    use super::{generate, url_path};
+    use crate::api::{filter::PostFilter, testing::TestArchive};
+
+    fn post(archive: &TestArchive, title: &str) -> PostId {
+        let post = UnsyncPost::<()>::new(
+            PlatformId(0),
+            format!("https://example.com/{title}"),
+            title.to_string(),
+            vec![],
+        );
+        archive.import_post(post, false).unwrap().0
+    }
+
+    fn file(archive: &TestArchive, post: PostId, name: &str) -> FileMetaId {
+        let file_meta = UnsyncFileMeta::new(name.to_string(), "image/png".to_string(), ());
+        let id = archive.import_file_meta(post, &file_meta).unwrap();
+        let path = archive
+            .path
+            .join(archive.get_file_meta(id).unwrap().unwrap().path());
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, name).unwrap();
+        id
+    }
+
+    #[test]
+    fn writes_pages_and_copies_files() {
+        let archive = TestArchive::new();
+        let id = post(&archive, "<Tom & \"Jerry\">");
+        let own = file(&archive, id, "a b.png");
+        let other = post(&archive, "other");
+        let shared = file(&archive, other, "shared.png");
+        let author = archive
+            .import_author(UnsyncAuthor::new("someone".to_string()))
+            .unwrap();
+        archive.bind(id).add_authors(&[author]).unwrap();
+        archive
+            .bind(id)
+            .update(UpdatePost::default().content(vec![
+                Content::Text("<script>".to_string()),
+                Content::File(own),
+                Content::File(shared),
+            ]))
+            .unwrap();
+
+        let output = archive.path.join("site");
+        let report = generate(&archive, &PostFilter::default(), &output, 1).unwrap();
+        assert_eq!((report.posts, report.files), (2, 2));
+
+        let page = fs::read_to_string(output.join(format!("posts/{}.html", id.0))).unwrap();
+        assert!(page.contains("<h1>&lt;Tom &amp; &quot;Jerry&quot;&gt;</h1>"));
+        assert!(page.contains("&lt;script&gt;"));
+        assert!(!page.contains("<script>"));
+        assert!(page.contains("href=\"../style.css\""));
+        assert!(page.contains(&format!("href=\"../authors/{}.html\"", author.0)));
+        let dir = |post: PostId| url_path(&Post::directory(post));
+        assert!(page.contains(&format!("src=\"../files/{}/a%20b.png\"", dir(id))));
+        assert!(page.contains(&format!("src=\"../files/{}/shared.png\"", dir(other))));
+
+        // one post per page, the index is split in two
+        let first = fs::read_to_string(output.join("index.html")).unwrap();
+        assert!(first.contains("<a href=\"index-2.html\">Next</a>"));
+        assert!(first.contains("href=\"style.css\""));
+        let second = fs::read_to_string(output.join("index-2.html")).unwrap();
+        assert!(second.contains("<a href=\"index.html\">Previous</a>"));
+        assert!(!output.join("index-3.html").exists());
+
+        let authors = fs::read_to_string(output.join("authors/index.html")).unwrap();
+        assert!(authors.contains(&format!("<a href=\"{}.html\">someone</a>", author.0)));
+        let listed = fs::read_to_string(output.join(format!("authors/{}.html", author.0))).unwrap();
+        assert!(listed.contains(&format!("href=\"../posts/{}.html\"", id.0)));
+
+        let copied = |post: PostId, name: &str| {
+            let path = output.join("files").join(Post::directory(post)).join(name);
+            fs::read_to_string(path).unwrap()
+        };
+        assert_eq!(copied(id, "a b.png"), "a b.png");
+        assert_eq!(copied(other, "shared.png"), "shared.png");
+    }
+}
@@ -0,0 +1,303 @@
+use std::{
+    fs,
+    io::{self, BufReader},
+    path::Path as FsPath,
+};
+
+use axum::{
+    Router,
+    extract::State,
+    http::StatusCode,
+    routing::{get, post},
+};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use post_archiver::manager::PostArchiverManager;
+use rusqlite::{Connection, DatabaseName, params};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use ts_rs::TS;
+
+use super::{
+    AppState,
+    error::{ApiError, ApiErrorCode, ApiResult},
+    extract::{Json, Path, Query},
+    maintenance::walk_archive,
+    search,
+    state::{EDITOR_DB, attach_editor_db},
+};
+
+pub const SNAPSHOT_DIR: &str = ".snapshots";
+const ARCHIVE_DB: &str = "post-archiver.db";
+const MANIFEST: &str = "manifest.json";
+const NAME_FORMAT: &str = "%Y%m%d-%H%M%S-%3f";
+
+pub fn wrap_snapshot_route(router: Router<AppState>) -> Router<AppState> {
+    router
+        .route(
+            "/snapshots",
+            get(list_snapshots_handler).post(create_snapshot_handler),
+        )
+        .route("/snapshots/{name}/restore", post(restore_snapshot_handler))
+}
+
+#[derive(Debug, Clone, Serialize, TS)]
+#[ts(export)]
+pub struct SnapshotInfo {
+    pub name: String,
+    pub created: DateTime<Utc>,
+    pub size: u64,
+    pub manifest: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct Manifest {
+    pub files: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, TS)]
+#[ts(export)]
+pub struct ManifestEntry {
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct CreateSnapshotPayload {
+    #[serde(default)]
+    pub manifest: bool,
+}
+
+#[derive(Debug, Serialize, TS)]
+#[ts(export)]
+pub struct RestoreReport {
+    pub restored: SnapshotInfo,
+    // taken right before restoring so the restore itself can be undone
+    pub backup: SnapshotInfo,
+    // archive files that differ from the manifest, empty without one
+    pub changed_files: Vec<String>,
+}
+
+async fn list_snapshots_handler(
+    State(state): State<AppState>,
+) -> ApiResult<Json<Vec<SnapshotInfo>>> {
+    state.read(|manager| Ok(Json(list(&manager.path)?))).await
+}
+
+async fn create_snapshot_handler(
+    State(state): State<AppState>,
+    Query(payload): Query<CreateSnapshotPayload>,
+) -> ApiResult<(StatusCode, Json<SnapshotInfo>)> {
+    let info = state
+        .read(move |manager| create(manager, payload.manifest))
+        .await?;
+    Ok((StatusCode::CREATED, Json(info)))
+}
+
+async fn restore_snapshot_handler(
+    Path(name): Path<String>,
+    State(state): State<AppState>,
+) -> ApiResult<Json<RestoreReport>> {
+    state
+        .write(move |manager| {
+            let restored = find(&manager.path, &name)?;
+            let backup = create(manager, false)?;
+            restore(manager, &name)?;
+
+            let changed_files = if restored.manifest {
+                verify(&manager.path, &name)?
+            } else {
+                vec![]
+            };
+            Ok(Json(RestoreReport {
+                restored,
+                backup,
+                changed_files,
+            }))
+        })
+        .await
+}
+
+// copies both databases with SQLite's online backup, so writers are never blocked
+pub fn create(manager: &PostArchiverManager, manifest: bool) -> ApiResult<SnapshotInfo> {
+    let name = Utc::now().format(NAME_FORMAT).to_string();
+    let dir = manager.path.join(SNAPSHOT_DIR).join(&name);
+    fs::create_dir_all(&dir)?;
+
+    let conn = manager.conn();
+    let result = conn
+        .backup(DatabaseName::Main, dir.join(ARCHIVE_DB), None)
+        .and_then(|()| conn.backup(DatabaseName::Attached("editor"), dir.join(EDITOR_DB), None))
+        .map_err(ApiError::from)
+        .and_then(|()| {
+            if manifest {
+                write_manifest(&manager.path, &dir)?;
+            }
+            Ok(())
+        });
+    if let Err(err) = result {
+        fs::remove_dir_all(&dir).ok();
+        return Err(err);
+    }
+
+    find(&manager.path, &name)
+}
+
+pub fn create_at(root: &FsPath, manifest: bool) -> ApiResult<SnapshotInfo> {
+    let manager = PostArchiverManager::open(root)?
+        .ok_or_else(|| ApiError::internal("post archiver database not found"))?;
+    attach_editor_db(manager.conn(), root, true)?;
+    create(&manager, manifest)
+}
+
+pub fn list(root: &FsPath) -> ApiResult<Vec<SnapshotInfo>> {
+    let dir = root.join(SNAPSHOT_DIR);
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut snapshots = vec![];
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+        if let Ok(info) = find(root, &name) {
+            snapshots.push(info);
+        }
+    }
+    snapshots.sort_by_key(|snapshot| std::cmp::Reverse(snapshot.created));
+    Ok(snapshots)
+}
+
+fn find(root: &FsPath, name: &str) -> ApiResult<SnapshotInfo> {
+    let not_found = || {
+        ApiError::new(
+            ApiErrorCode::NotFound,
+            format!("snapshot {name:?} not found"),
+        )
+    };
+    let created = NaiveDateTime::parse_from_str(name, NAME_FORMAT)
+        .map_err(|_| not_found())?
+        .and_utc();
+
+    let dir = root.join(SNAPSHOT_DIR).join(name);
+    let size = fs::metadata(dir.join(ARCHIVE_DB))
+        .map_err(|_| not_found())?
+        .len();
+    Ok(SnapshotInfo {
+        name: name.to_string(),
+        created,
+        size,
+        manifest: dir.join(MANIFEST).is_file(),
+    })
+}
+
+// the live connections stay open, so rows are copied back inside one
+// transaction instead of swapping the database file
+// the history goes back together with the archive it describes, the search
+// index is derived from the posts and rebuilt instead of copied
+fn restore(manager: &PostArchiverManager, name: &str) -> ApiResult<()> {
+    let dir = manager.path.join(SNAPSHOT_DIR).join(name);
+    // attaching a missing file would create it and restore an empty history
+    if !dir.join(EDITOR_DB).exists() {
+        return Err(ApiError::new(
+            ApiErrorCode::Conflict,
+            format!("snapshot {name:?} has no editor database"),
+        ));
+    }
+
+    let conn = manager.conn();
+    conn.execute(
+        "ATTACH DATABASE ?1 AS snapshot",
+        params![dir.join(ARCHIVE_DB).to_string_lossy()],
+    )?;
+    let result = conn
+        .execute(
+            "ATTACH DATABASE ?1 AS snapshot_editor",
+            params![dir.join(EDITOR_DB).to_string_lossy()],
+        )
+        .map_err(ApiError::from)
+        .and_then(|_| {
+            let result = copy_tables(conn);
+            conn.execute("DETACH DATABASE snapshot_editor", [])?;
+            result
+        });
+    conn.execute("DETACH DATABASE snapshot", [])?;
+    result?;
+
+    search::sync(manager)
+}
+
+fn copy_tables(conn: &Connection) -> ApiResult<()> {
+    let tables = |schema: &str| -> rusqlite::Result<Vec<String>> {
+        conn.prepare(&format!(
+            "SELECT name FROM {schema}.sqlite_master
+            WHERE type = 'table' AND name NOT LIKE 'sqlite_%' AND name NOT LIKE 'post_search%'"
+        ))?
+        .query_map([], |row| row.get(0))?
+        .collect()
+    };
+    let tables: Vec<_> = (tables("snapshot")?.into_iter())
+        .map(|table| ("main", "snapshot", table))
+        .chain(
+            tables("snapshot_editor")?
+                .into_iter()
+                .map(|table| ("editor", "snapshot_editor", table)),
+        )
+        .collect();
+
+    let tx = conn.unchecked_transaction()?;
+    conn.pragma_update(None, "defer_foreign_keys", true)?;
+    for (target, _, table) in &tables {
+        conn.execute(&format!("DELETE FROM {target}.\"{table}\""), [])?;
+    }
+    for (target, source, table) in &tables {
+        conn.execute(
+            &format!("INSERT INTO {target}.\"{table}\" SELECT * FROM {source}.\"{table}\""),
+            [],
+        )?;
+    }
+    conn.execute_batch(
+        "DELETE FROM editor.post_search;
+        DELETE FROM editor.post_search_state;",
+    )?;
+    tx.commit()?;
+    Ok(())
+}
+
+fn write_manifest(root: &FsPath, dir: &FsPath) -> ApiResult<()> {
+    let (files, _) = walk_archive(root)?;
+    let files = files
+        .iter()
+        .map(|path| hash_entry(root, path))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let manifest = serde_json::to_vec_pretty(&Manifest { files }).map_err(ApiError::internal)?;
+    fs::write(dir.join(MANIFEST), manifest)?;
+    Ok(())
+}
+
+fn verify(root: &FsPath, name: &str) -> ApiResult<Vec<String>> {
+    let manifest = fs::read(root.join(SNAPSHOT_DIR).join(name).join(MANIFEST))?;
+    let manifest: Manifest = serde_json::from_slice(&manifest).map_err(ApiError::internal)?;
+
+    let mut changed = vec![];
+    for entry in manifest.files {
+        let current = hash_entry(root, FsPath::new(&entry.path)).ok();
+        if current.as_ref() != Some(&entry) {
+            changed.push(entry.path);
+        }
+    }
+    Ok(changed)
+}
+
+fn hash_entry(root: &FsPath, path: &FsPath) -> io::Result<ManifestEntry> {
+    let file = fs::File::open(root.join(path))?;
+    let size = file.metadata()?.len();
+    let mut hasher = Sha256::new();
+    io::copy(&mut BufReader::new(file), &mut hasher)?;
+    Ok(ManifestEntry {
+        path: path.to_string_lossy().into_owned(),
+        size,
+        sha256: format!("{:x}", hasher.finalize()),
+    })
+}
@@ -0,0 +1,285 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex},
+};
+
+use post_archiver::manager::PostArchiverManager;
+use rusqlite::{Connection, params};
+
+use super::{
+    error::{ApiError, ApiErrorCode, ApiResult},
+    history, search,
+};
+
+pub const EDITOR_DB: &str = "post-archiver-editor.db";
+
+#[derive(Clone)]
+pub struct AppState {
+    path: Arc<PathBuf>,
+    readers: Arc<ManagerPool>,
+    writer: Arc<Mutex<PostArchiverManager>>,
+    read_only: bool,
+    public_url: Arc<str>,
+}
+
+impl AppState {
+    pub fn new(path: PathBuf, readers: usize, read_only: bool) -> ApiResult<Self> {
+        let writer = open_manager(&path)?;
+        attach_editor_db(writer.conn(), &path, !read_only)?;
+        if read_only {
+            writer.conn().pragma_update(None, "query_only", true)?;
+        } else {
+            // WAL lets the reader pool keep serving while the writer holds a transaction
+            writer
+                .conn()
+                .pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+            search::sync(&writer)?;
+        }
+
+        Ok(Self {
+            readers: Arc::new(ManagerPool::new(path.clone(), readers.max(1))),
+            path: Arc::new(path),
+            writer: Arc::new(Mutex::new(writer)),
+            read_only,
+            public_url: Arc::from("http://localhost"),
+        })
+    }
+
+    pub fn with_public_url(self, url: &str) -> Self {
+        Self {
+            public_url: Arc::from(url.trim_end_matches('/')),
+            ..self
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn public_url(&self) -> &str {
+        &self.public_url
+    }
+
+    pub async fn read<F, R>(&self, f: F) -> ApiResult<R>
+    where
+        F: FnOnce(&PostArchiverManager) -> ApiResult<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let readers = self.readers.clone();
+        tokio::task::spawn_blocking(move || {
+            let manager = readers.acquire()?;
+            f(&manager)
+        })
+        .await
+        .map_err(ApiError::internal)?
+    }
+
+    pub async fn write<F, R>(&self, f: F) -> ApiResult<R>
+    where
+        F: FnOnce(&PostArchiverManager) -> ApiResult<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        if self.read_only {
+            return Err(ApiErrorCode::ReadOnly.into());
+        }
+
+        let writer = self.writer.clone();
+        tokio::task::spawn_blocking(move || {
+            let manager = writer.lock().unwrap();
+            f(&manager)
+        })
+        .await
+        .map_err(ApiError::internal)?
+    }
+
+    pub async fn transaction<F, R>(&self, f: F) -> ApiResult<R>
+    where
+        F: FnOnce(&PostArchiverManager) -> ApiResult<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        self.write(move |manager| {
+            // dropping the transaction on error rolls every change back
+            let tx = manager.conn().unchecked_transaction()?;
+            let result = f(manager)?;
+            tx.commit()?;
+            Ok(result)
+        })
+        .await
+    }
+}
+
+// editor-owned tables live next to the archive so post-archiver.db stays untouched,
+// without `create` an existing database is attached read-only and a missing one is skipped
+pub(super) fn attach_editor_db(
+    conn: &Connection,
+    root: &Path,
+    create: bool,
+) -> rusqlite::Result<()> {
+    let path = root.join(EDITOR_DB);
+    if !create {
+        if !path.exists() {
+            return Ok(());
+        }
+        let uri = path
+            .to_string_lossy()
+            .replace('%', "%25")
+            .replace('?', "%3f")
+            .replace('#', "%23");
+        conn.execute(
+            "ATTACH DATABASE ?1 AS editor",
+            params![format!("file:{uri}?mode=ro")],
+        )?;
+        return Ok(());
+    }
+
+    conn.execute(
+        "ATTACH DATABASE ?1 AS editor",
+        params![path.to_string_lossy()],
+    )?;
+    history::init(conn)?;
+    search::init(conn)?;
+    Ok(())
+}
+
+pub(super) fn has_editor_db(conn: &Connection) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM pragma_database_list WHERE name = 'editor')",
+        [],
+        |row| row.get(0),
+    )
+}
+
+fn open_manager(path: &Path) -> ApiResult<PostArchiverManager> {
+    PostArchiverManager::open(path)?
+        .ok_or_else(|| ApiError::internal("post archiver database not found"))
+}
+
+struct ManagerPool {
+    path: PathBuf,
+    max: usize,
+    state: Mutex<PoolState>,
+    available: Condvar,
+}
+
+#[derive(Default)]
+struct PoolState {
+    idle: Vec<PostArchiverManager>,
+    opened: usize,
+}
+
+impl ManagerPool {
+    fn new(path: PathBuf, max: usize) -> Self {
+        Self {
+            path,
+            max,
+            state: Default::default(),
+            available: Condvar::new(),
+        }
+    }
+
+    fn acquire(&self) -> ApiResult<PooledManager<'_>> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(manager) = state.idle.pop() {
+                return Ok(PooledManager {
+                    pool: self,
+                    manager: Some(manager),
+                });
+            }
+
+            if state.opened < self.max {
+                state.opened += 1;
+                drop(state);
+                return match self.open() {
+                    Ok(manager) => Ok(PooledManager {
+                        pool: self,
+                        manager: Some(manager),
+                    }),
+                    Err(err) => {
+                        self.state.lock().unwrap().opened -= 1;
+                        self.available.notify_one();
+                        Err(err)
+                    }
+                };
+            }
+
+            state = self.available.wait(state).unwrap();
+        }
+    }
+
+    fn open(&self) -> ApiResult<PostArchiverManager> {
+        let manager = open_manager(&self.path)?;
+        attach_editor_db(manager.conn(), &self.path, false)?;
+        manager.conn().pragma_update(None, "query_only", true)?;
+        Ok(manager)
+    }
+
+    fn release(&self, manager: PostArchiverManager) {
+        self.state.lock().unwrap().idle.push(manager);
+        self.available.notify_one();
+    }
+}
+
+struct PooledManager<'a> {
+    pool: &'a ManagerPool,
+    manager: Option<PostArchiverManager>,
+}
+
+impl std::ops::Deref for PooledManager<'_> {
+    type Target = PostArchiverManager;
+
+    fn deref(&self) -> &Self::Target {
+        self.manager.as_ref().unwrap()
+    }
+}
+
+impl Drop for PooledManager<'_> {
+    fn drop(&mut self) {
+        if let Some(manager) = self.manager.take() {
+            self.pool.release(manager);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use post_archiver::manager::PostArchiverManager;
+
+    use super::{EDITOR_DB, attach_editor_db, has_editor_db};
+    use crate::api::testing::TestArchive;
+
+    fn reader(archive: &TestArchive) -> PostArchiverManager {
+        let manager = PostArchiverManager::open(&archive.path).unwrap().unwrap();
+        attach_editor_db(manager.conn(), &archive.path, false).unwrap();
+        manager
+    }
+
+    #[test]
+    fn read_only_attach_cannot_write() {
+        let archive = TestArchive::new();
+        let manager = reader(&archive);
+
+        assert!(has_editor_db(manager.conn()).unwrap());
+        let insert = manager.conn().execute(
+            "INSERT INTO editor.revisions (kind, entity, action, snapshot, created)
+            VALUES ('posts', 1, 'update', '{}', '2024-01-01T00:00:00Z')",
+            [],
+        );
+        assert!(insert.is_err());
+    }
+
+    #[test]
+    fn read_only_attach_skips_a_missing_database() {
+        let archive = TestArchive::new();
+        let path = archive.path.join(EDITOR_DB);
+        std::fs::remove_file(&path).unwrap();
+
+        let manager = reader(&archive);
+        assert!(!has_editor_db(manager.conn()).unwrap());
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,330 @@
+use std::{
+    io::{self, Read, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// a minimal ustar writer/reader for bundles, long paths and sizes of 8 GiB and
+// up use pax headers
+
+const BLOCK: usize = 512;
+// the largest size the 11 octal digits of the header field can hold
+const MAX_SIZE: u64 = 0o77777777777;
+
+pub struct TarWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> TarWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner }
+    }
+
+    pub fn append_bytes(&mut self, path: &str, data: &[u8]) -> io::Result<()> {
+        self.append(path, data.len() as u64, &mut &data[..])
+    }
+
+    pub fn append(&mut self, path: &str, size: u64, reader: &mut impl Read) -> io::Result<()> {
+        let mut records = vec![];
+        if path.len() > 100 {
+            records.extend(pax_record("path", path));
+        }
+        if size > MAX_SIZE {
+            records.extend(pax_record("size", &size.to_string()));
+        }
+        if !records.is_empty() {
+            self.inner
+                .write_all(&header("PaxHeader", records.len() as u64, b'x'))?;
+            self.inner.write_all(&records)?;
+            self.pad(records.len() as u64)?;
+        }
+
+        self.inner.write_all(&header(path, size, b'0'))?;
+        let copied = io::copy(&mut reader.take(size), &mut self.inner)?;
+        if copied != size {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("{path} shrank while being archived"),
+            ));
+        }
+        self.pad(size)
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.write_all(&[0; BLOCK * 2])?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn pad(&mut self, size: u64) -> io::Result<()> {
+        let rest = (size % BLOCK as u64) as usize;
+        if rest != 0 {
+            self.inner.write_all(&[0; BLOCK][rest..])?;
+        }
+        Ok(())
+    }
+}
+
+fn header(path: &str, size: u64, kind: u8) -> [u8; BLOCK] {
+    let mut header = [0; BLOCK];
+    // names longer than the field are carried by the preceding pax header
+    let name = &path.as_bytes()[..path.len().min(100)];
+    header[..name.len()].copy_from_slice(name);
+    octal(&mut header[100..108], 0o644);
+    octal(&mut header[108..116], 0);
+    octal(&mut header[116..124], 0);
+    // a size too wide for the field is carried by the preceding pax header
+    octal(
+        &mut header[124..136],
+        if size > MAX_SIZE { 0 } else { size },
+    );
+    let mtime = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or_default();
+    octal(&mut header[136..148], mtime);
+    header[156] = kind;
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+
+    header[148..156].fill(b' ');
+    let checksum: u64 = header.iter().map(|&byte| byte as u64).sum();
+    octal(&mut header[148..155], checksum);
+    header
+}
+
+fn octal(field: &mut [u8], value: u64) {
+    let width = field.len() - 1;
+    field[..width].copy_from_slice(format!("{value:0width$o}").as_bytes());
+    field[width] = 0;
+}
+
+// "<length> <key>=<value>\n" where the length counts itself
+fn pax_record(key: &str, value: &str) -> Vec<u8> {
+    let rest = key.len() + value.len() + 3;
+    let mut len = rest + 1;
+    while len != rest + len.to_string().len() {
+        len = rest + len.to_string().len();
+    }
+    format!("{len} {key}={value}\n").into_bytes()
+}
+
+// pax headers only carry metadata, the cap keeps a forged size from filling memory
+const MAX_PAX: u64 = 64 * 1024;
+
+// an entry borrows the reader and streams its data, the rest is skipped on the next entry
+pub struct TarEntry<'a, R: Read> {
+    pub path: String,
+    reader: &'a mut TarReader<R>,
+}
+
+impl<R: Read> Read for TarEntry<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let reader = &mut *self.reader;
+        let max = (buf.len() as u64).min(reader.remaining) as usize;
+        if max == 0 {
+            return Ok(0);
+        }
+        let read = reader.inner.read(&mut buf[..max])?;
+        if read == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        reader.remaining -= read as u64;
+        Ok(read)
+    }
+}
+
+pub struct TarReader<R: Read> {
+    inner: R,
+    // unread data of the current entry and the padding after it
+    remaining: u64,
+    padding: u64,
+}
+
+impl<R: Read> TarReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            remaining: 0,
+            padding: 0,
+        }
+    }
+
+    // regular files only, other entry kinds are skipped
+    pub fn next_entry(&mut self) -> io::Result<Option<TarEntry<'_, R>>> {
+        self.skip_rest()?;
+
+        let mut pax = Pax::default();
+        loop {
+            let mut header = [0; BLOCK];
+            self.inner.read_exact(&mut header)?;
+            if header.iter().all(|&byte| byte == 0) {
+                return Ok(None);
+            }
+
+            let size = parse_octal(&header[124..136])?;
+            let rest = size % BLOCK as u64;
+            self.remaining = size;
+            self.padding = if rest == 0 { 0 } else { BLOCK as u64 - rest };
+
+            match header[156] {
+                b'x' => {
+                    if size > MAX_PAX {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "pax header is too large",
+                        ));
+                    }
+                    let mut data = vec![];
+                    (&mut self.inner).take(size).read_to_end(&mut data)?;
+                    if data.len() as u64 != size {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                    self.remaining = 0;
+                    self.skip_rest()?;
+                    pax = parse_pax(&data);
+                }
+                b'0' | 0 => {
+                    if let Some(size) = pax.size {
+                        let rest = size % BLOCK as u64;
+                        self.remaining = size;
+                        self.padding = if rest == 0 { 0 } else { BLOCK as u64 - rest };
+                    }
+                    let path = pax.path.take().unwrap_or_else(|| {
+                        let name = &header[..100];
+                        let end = name.iter().position(|&byte| byte == 0).unwrap_or(100);
+                        String::from_utf8_lossy(&name[..end]).into_owned()
+                    });
+                    return Ok(Some(TarEntry { path, reader: self }));
+                }
+                _ => {
+                    pax = Pax::default();
+                    self.skip_rest()?;
+                }
+            }
+        }
+    }
+
+    fn skip_rest(&mut self) -> io::Result<()> {
+        let skip = self.remaining + self.padding;
+        let skipped = io::copy(&mut (&mut self.inner).take(skip), &mut io::sink())?;
+        if skipped != skip {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining = 0;
+        self.padding = 0;
+        Ok(())
+    }
+}
+
+fn parse_octal(field: &[u8]) -> io::Result<u64> {
+    let text = String::from_utf8_lossy(field);
+    let text = text.trim_matches(|c: char| c == '\0' || c == ' ');
+    if text.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(text, 8).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+// the records of a pax header that apply to the next entry
+#[derive(Default)]
+struct Pax {
+    path: Option<String>,
+    size: Option<u64>,
+}
+
+fn parse_pax(data: &[u8]) -> Pax {
+    let mut pax = Pax::default();
+    for line in String::from_utf8_lossy(data).lines() {
+        let Some((key, value)) = line
+            .split_once(' ')
+            .and_then(|(_, record)| record.split_once('='))
+        else {
+            continue;
+        };
+        match key {
+            "path" => pax.path = Some(value.to_string()),
+            "size" => pax.size = value.parse().ok(),
+            _ => {}
+        }
+    }
+    pax
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::{TarReader, TarWriter};
+
+    fn bundle(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = TarWriter::new(vec![]);
+        for (path, data) in entries {
+            writer.append_bytes(path, data).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let long = format!("files/1/{}.txt", "a".repeat(120));
+        let data = bundle(&[
+            ("manifest.json", b"{}"),
+            (&long, &[7; 1000]),
+            ("empty", b""),
+        ]);
+
+        let mut reader = TarReader::new(&data[..]);
+        let mut read = vec![];
+        while let Some(mut entry) = reader.next_entry().unwrap() {
+            let mut content = vec![];
+            entry.read_to_end(&mut content).unwrap();
+            read.push((entry.path.clone(), content));
+        }
+
+        assert_eq!(
+            read,
+            vec![
+                ("manifest.json".to_string(), b"{}".to_vec()),
+                (long, vec![7; 1000]),
+                ("empty".to_string(), vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_unread_data() {
+        let data = bundle(&[("first", &[1; 700]), ("second", b"kept")]);
+
+        let mut reader = TarReader::new(&data[..]);
+        let mut first = reader.next_entry().unwrap().unwrap();
+        first.read_exact(&mut [0; 10]).unwrap();
+
+        let mut second = reader.next_entry().unwrap().unwrap();
+        let mut content = String::new();
+        second.read_to_string(&mut content).unwrap();
+        assert_eq!((second.path.as_str(), content.as_str()), ("second", "kept"));
+        assert!(reader.next_entry().unwrap().is_none());
+    }
+
+    #[test]
+    fn large_sizes_use_a_pax_record() {
+        let size = 8 << 30;
+        let mut writer = TarWriter::new(vec![]);
+        // only the headers matter, the missing data makes the append fail after them
+        assert!(writer.append("big", size, &mut &[1; 10][..]).is_err());
+
+        let mut reader = TarReader::new(&writer.inner[..]);
+        let entry = reader.next_entry().unwrap().unwrap();
+        assert_eq!(entry.path, "big");
+        assert_eq!(entry.reader.remaining, size);
+    }
+
+    #[test]
+    fn truncated_entry_is_an_error() {
+        let data = bundle(&[("file", &[1; 2000])]);
+
+        let mut reader = TarReader::new(&data[..1024]);
+        let mut entry = reader.next_entry().unwrap().unwrap();
+        assert!(entry.read_to_end(&mut vec![]).is_err());
+    }
+}
@@ -0,0 +1,45 @@
+use std::{
+    ops::Deref,
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use post_archiver::manager::PostArchiverManager;
+
+use super::state::attach_editor_db;
+
+static ARCHIVES: AtomicU64 = AtomicU64::new(0);
+
+// a fresh archive with the editor database attached, removed again on drop
+pub struct TestArchive {
+    manager: PostArchiverManager,
+    path: PathBuf,
+}
+
+impl TestArchive {
+    pub fn new() -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "post-archiver-editor-test-{}-{}",
+            std::process::id(),
+            ARCHIVES.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&path).unwrap();
+        let manager = PostArchiverManager::create(&path).unwrap();
+        attach_editor_db(manager.conn(), &path, true).unwrap();
+        Self { manager, path }
+    }
+}
+
+impl Deref for TestArchive {
+    type Target = PostArchiverManager;
+
+    fn deref(&self) -> &Self::Target {
+        &self.manager
+    }
+}
+
+impl Drop for TestArchive {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.path).ok();
+    }
+}
@@ -1,5 +1,9 @@
-use post_archiver::{AuthorId, CollectionId, FileMetaId, PlatformId, TagId, query::Totalled};
+use post_archiver::{
+    AuthorId, CollectionId, FileMetaId, PlatformId, TagId,
+    query::{SortDir, Totalled},
+};
 use serde::{Deserialize, Serialize};
+use ts_rs::TS;
 
 use super::relation::RequireRelations;
 
@@ -19,6 +23,40 @@ impl Pagination {
     }
 }
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, TS)]
+#[serde(rename_all = "snake_case")]
+#[ts(export)]
+pub enum SortDirection {
+    Asc,
+    #[default]
+    Desc,
+}
+
+impl SortDirection {
+    pub fn sql(self) -> &'static str {
+        match self {
+            SortDirection::Asc => "ASC",
+            SortDirection::Desc => "DESC",
+        }
+    }
+}
+
+impl From<SortDirection> for SortDir {
+    fn from(dir: SortDirection) -> Self {
+        match dir {
+            SortDirection::Asc => SortDir::Asc,
+            SortDirection::Desc => SortDir::Desc,
+        }
+    }
+}
+
+// pairs with `ESCAPE '\'` in LIKE patterns
+pub fn escape_like(text: &str) -> String {
+    text.replace('\\', r"\\")
+        .replace('%', r"\%")
+        .replace('_', r"\_")
+}
+
 impl<T: RequireRelations> RequireRelations for Totalled<Vec<T>> {
     fn authors(&self) -> Vec<AuthorId> {
         self.items.authors()
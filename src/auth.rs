@@ -0,0 +1,270 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    Router,
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::{
+    api::{
+        error::{ApiError, ApiErrorCode, ApiResult},
+        extract::Json,
+    },
+    config::Config,
+};
+
+const SESSION_COOKIE: &str = "editor_session";
+const SESSION_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+// failed sign-ins an address gets before it has to wait out the lockout
+const MAX_FAILURES: u32 = 5;
+const LOCKOUT: Duration = Duration::from_secs(60);
+
+#[derive(Clone)]
+pub struct Auth {
+    password: Option<Arc<str>>,
+    token: Option<Arc<str>>,
+    sessions: Arc<Mutex<HashMap<String, Instant>>>,
+    failures: Arc<Mutex<HashMap<IpAddr, Failures>>>,
+}
+
+struct Failures {
+    count: u32,
+    last: Instant,
+}
+
+impl Auth {
+    pub fn new(config: &Config) -> Self {
+        let password = config.password.as_deref().map(Arc::from);
+        let token = config.token.as_deref().map(Arc::from);
+        Self {
+            password,
+            token,
+            sessions: Default::default(),
+            failures: Default::default(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.password.is_some() || self.token.is_some()
+    }
+
+    fn bearer(&self) -> Option<&str> {
+        self.token.as_deref().or(self.password.as_deref())
+    }
+
+    fn is_authenticated(&self, headers: &HeaderMap) -> bool {
+        if !self.enabled() {
+            return true;
+        }
+
+        if let Some(bearer) = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+        {
+            return self
+                .bearer()
+                .is_some_and(|token| constant_time_eq(token.as_bytes(), bearer.trim().as_bytes()));
+        }
+
+        let Some(session) = session_cookie(headers) else {
+            return false;
+        };
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, created| created.elapsed() < SESSION_TTL);
+        sessions.contains_key(session)
+    }
+
+    fn create_session(&self) -> ApiResult<String> {
+        let session = random_token()?;
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(session.clone(), Instant::now());
+        Ok(session)
+    }
+
+    // time left until `ip` may try again, failures are forgotten a lockout after the last one
+    fn locked_out(&self, ip: IpAddr) -> Option<Duration> {
+        let mut failures = self.failures.lock().unwrap();
+        failures.retain(|_, failures| failures.last.elapsed() < LOCKOUT);
+        failures
+            .get(&ip)
+            .filter(|failures| failures.count >= MAX_FAILURES)
+            .map(|failures| LOCKOUT.saturating_sub(failures.last.elapsed()))
+    }
+
+    fn record_failure(&self, ip: IpAddr) {
+        let mut failures = self.failures.lock().unwrap();
+        let failures = failures.entry(ip).or_insert(Failures {
+            count: 0,
+            last: Instant::now(),
+        });
+        failures.count += 1;
+        failures.last = Instant::now();
+    }
+
+    fn clear_failures(&self, ip: IpAddr) {
+        self.failures.lock().unwrap().remove(&ip);
+    }
+
+    fn remove_session(&self, headers: &HeaderMap) {
+        if let Some(session) = session_cookie(headers) {
+            self.sessions.lock().unwrap().remove(session);
+        }
+    }
+}
+
+pub async fn require_auth(State(auth): State<Auth>, request: Request, next: Next) -> Response {
+    if auth.is_authenticated(request.headers()) {
+        next.run(request).await
+    } else {
+        ApiError::from(ApiErrorCode::Unauthorized).into_response()
+    }
+}
+
+pub fn get_auth_router<S>(auth: Auth) -> Router<S> {
+    Router::new()
+        .route("/auth", get(auth_status_handler))
+        .route("/auth/login", post(login_handler))
+        .route("/auth/logout", post(logout_handler))
+        .with_state(auth)
+}
+
+#[derive(Debug, Serialize, TS)]
+#[ts(export)]
+pub struct AuthStatus {
+    pub enabled: bool,
+    pub authenticated: bool,
+}
+
+async fn auth_status_handler(State(auth): State<Auth>, headers: HeaderMap) -> Json<AuthStatus> {
+    Json(AuthStatus {
+        enabled: auth.enabled(),
+        authenticated: auth.is_authenticated(&headers),
+    })
+}
+
+#[derive(Debug, Deserialize, TS)]
+#[ts(export)]
+pub struct LoginPayload {
+    pub password: String,
+}
+
+async fn login_handler(
+    State(auth): State<Auth>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Json(payload): Json<LoginPayload>,
+) -> ApiResult<Response> {
+    let Some(password) = auth.password.as_deref() else {
+        return Err(ApiError::new(
+            ApiErrorCode::NotFound,
+            "password sign-in is not enabled",
+        ));
+    };
+    if let Some(wait) = auth.locked_out(peer.ip()) {
+        let wait = wait.as_secs().max(1);
+        let err = ApiError::new(
+            ApiErrorCode::TooManyRequests,
+            format!("too many failed sign-ins, try again in {wait} seconds"),
+        );
+        return Ok(([(header::RETRY_AFTER, wait.to_string())], err).into_response());
+    }
+    if !constant_time_eq(password.as_bytes(), payload.password.as_bytes()) {
+        auth.record_failure(peer.ip());
+        return Err(
+            ApiError::new(ApiErrorCode::Unauthorized, "incorrect password").with_field("password"),
+        );
+    }
+    auth.clear_failures(peer.ip());
+
+    let session = auth.create_session()?;
+    let cookie = format!(
+        "{SESSION_COOKIE}={session}; Path=/; HttpOnly; SameSite=Strict; Max-Age={}",
+        SESSION_TTL.as_secs()
+    );
+    let cookie = HeaderValue::from_str(&cookie).map_err(ApiError::internal)?;
+    Ok(([(header::SET_COOKIE, cookie)], StatusCode::NO_CONTENT).into_response())
+}
+
+async fn logout_handler(State(auth): State<Auth>, headers: HeaderMap) -> impl IntoResponse {
+    auth.remove_session(&headers);
+    let cookie = format!("{SESSION_COOKIE}=; Path=/; HttpOnly; SameSite=Strict; Max-Age=0");
+    ([(header::SET_COOKIE, cookie)], StatusCode::NO_CONTENT)
+}
+
+fn session_cookie(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, value)| value)
+}
+
+// 32 bytes from the OS random source
+fn random_token() -> ApiResult<String> {
+    let mut bytes = [0; 32];
+    getrandom::fill(&mut bytes).map_err(ApiError::internal)?;
+    Ok(bytes.iter().map(|byte| format!("{byte:02x}")).collect())
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use super::{Auth, MAX_FAILURES, random_token};
+
+    fn auth() -> Auth {
+        Auth {
+            password: Some("secret".into()),
+            token: None,
+            sessions: Default::default(),
+            failures: Default::default(),
+        }
+    }
+
+    #[test]
+    fn tokens_are_random() {
+        let (a, b) = (random_token().unwrap(), random_token().unwrap());
+        assert_eq!(a.len(), 64);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn repeated_failures_lock_out_the_address() {
+        let auth = auth();
+        let (ip, other) = (
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+        );
+        for _ in 0..MAX_FAILURES - 1 {
+            auth.record_failure(ip);
+        }
+        assert!(auth.locked_out(ip).is_none());
+
+        auth.record_failure(ip);
+        assert!(auth.locked_out(ip).is_some());
+        assert!(auth.locked_out(other).is_none());
+
+        auth.clear_failures(ip);
+        assert!(auth.locked_out(ip).is_none());
+    }
+}
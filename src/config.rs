@@ -1,14 +1,113 @@
 use std::path::PathBuf;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use clap_verbosity_flag::InfoLevel;
 
+use crate::api::import::ConflictPolicy;
+
 #[derive(Debug, Clone, Parser)]
 pub struct Config {
     #[clap(env = "ARCHIVER_PATH", default_value = "archive")]
     pub path: PathBuf,
     #[clap(long, default_value = "3000")]
     pub port: u16,
+    /// Address the editor is reached at, used for absolute links in feeds
+    #[clap(long, env = "EDITOR_PUBLIC_URL")]
+    pub public_url: Option<String>,
+    /// Number of database connections used for read-only requests
+    #[clap(long, default_value = "4")]
+    pub readers: usize,
+    /// Serve the archive without any editing routes
+    #[clap(long, env = "EDITOR_READ_ONLY")]
+    pub read_only: bool,
+    /// Take a snapshot of the databases before serving
+    #[clap(long, env = "EDITOR_SNAPSHOT")]
+    pub snapshot: bool,
+    /// Include a manifest of file hashes in the startup snapshot
+    #[clap(long, requires = "snapshot")]
+    pub snapshot_manifest: bool,
+    /// Password required to sign in from the editor
+    #[clap(long, env = "EDITOR_PASSWORD", hide_env_values = true)]
+    pub password: Option<String>,
+    /// Bearer token accepted for scripted access (falls back to the password)
+    #[clap(long, env = "EDITOR_TOKEN", hide_env_values = true)]
+    pub token: Option<String>,
+    /// Allowed CORS origins, `*` allows any origin
+    #[clap(
+        long = "cors-origin",
+        env = "EDITOR_CORS_ORIGINS",
+        value_delimiter = ','
+    )]
+    pub cors_origins: Vec<String>,
     #[command(flatten)]
     pub verbosity: clap_verbosity_flag::Verbosity<InfoLevel>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+    /// Check the archive for broken references and stray files, then exit
+    Check {
+        /// Repair what can be repaired without losing data
+        #[arg(long)]
+        fix: bool,
+    },
+    /// List files and entities nothing refers to anymore, then exit
+    Gc {
+        /// Remove the listed entries instead of only listing them
+        #[arg(long)]
+        apply: bool,
+    },
+    /// Write posts with their files and relations to a tar bundle, then exit
+    Export {
+        /// Path of the bundle to write
+        output: PathBuf,
+        /// Post to export, can be repeated
+        #[arg(long = "post")]
+        posts: Vec<u32>,
+        /// Export every post of this author
+        #[arg(long)]
+        author: Vec<u32>,
+        /// Export every post with this tag
+        #[arg(long)]
+        tag: Vec<u32>,
+        /// Export every post in this collection
+        #[arg(long)]
+        collection: Vec<u32>,
+        /// Export every post from this platform
+        #[arg(long)]
+        platform: Vec<u32>,
+    },
+    /// Render posts and their author, tag and collection pages to static HTML, then exit
+    Site {
+        /// Directory to write the site to
+        output: PathBuf,
+        /// Number of posts on each list page
+        #[arg(long, default_value = "50")]
+        per_page: u64,
+        /// Only include posts of this author
+        #[arg(long)]
+        author: Vec<u32>,
+        /// Only include posts with this tag
+        #[arg(long)]
+        tag: Vec<u32>,
+        /// Only include posts in this collection
+        #[arg(long)]
+        collection: Vec<u32>,
+        /// Only include posts from this platform
+        #[arg(long)]
+        platform: Vec<u32>,
+    },
+    /// Recreate the posts of a tar bundle in this archive, then exit
+    Import {
+        /// Path of the bundle to read
+        bundle: PathBuf,
+        /// What to do with posts whose source already exists
+        #[arg(long, value_enum, default_value_t)]
+        policy: ConflictPolicy,
+        /// Only report what would be created
+        #[arg(long)]
+        preview: bool,
+    },
 }
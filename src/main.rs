@@ -1,9 +1,15 @@
 mod api;
+pub mod auth;
 pub mod config;
 pub mod frontend;
 pub mod resource;
 
 use api::get_api_router;
+use auth::{Auth, require_auth};
+use axum::{
+    http::{HeaderValue, header},
+    middleware,
+};
 use clap::Parser;
 use config::Config;
 use console::style;
@@ -18,10 +24,10 @@ use std::net::SocketAddr;
 use tower::ServiceBuilder;
 use tower_http::{
     compression::CompressionLayer,
-    cors::{Any, CorsLayer},
+    cors::{AllowHeaders, AllowMethods, AllowOrigin, Any, CorsLayer},
     trace::TraceLayer,
 };
-use tracing::{error, info};
+use tracing::{error, info, warn};
 use tracing_subscriber::fmt::{self, time::UtcTime};
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -57,12 +63,34 @@ async fn main() {
         return;
     }
 
+    if let Some(command) = config.command.clone() {
+        if let Err(err) = api::maintenance::run(&config, command).await {
+            error!("{}", err.message);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if config.snapshot {
+        match api::snapshot::create_at(&config.path, config.snapshot_manifest) {
+            Ok(snapshot) => info!("Snapshot {} created", style(snapshot.name).green()),
+            Err(err) => {
+                error!("Failed to create snapshot: {}", err.message);
+                return;
+            }
+        }
+    }
+
+    let auth = Auth::new(&config);
+    let auth_layer = middleware::from_fn_with_state(auth.clone(), require_auth);
+
     let images_router = get_images_router(
         config.path.clone(),
         ResizeConfig::builder().build().unwrap(),
-    );
-    let resource_router = get_resource_router(&config);
-    let api_router = get_api_router(&config);
+    )
+    .layer(auth_layer.clone());
+    let resource_router = get_resource_router(&config).layer(auth_layer);
+    let api_router = get_api_router(&config, &auth);
 
     let app = frontend()
         .nest("/api", api_router)
@@ -71,10 +99,17 @@ async fn main() {
         .layer(
             ServiceBuilder::new()
                 .layer(TraceLayer::new_for_http())
-                .layer(CorsLayer::new().allow_origin(Any))
+                .layer(cors_layer(&config))
                 .layer(CompressionLayer::new()),
         );
 
+    if !auth.enabled() {
+        warn!(
+            "Authentication is disabled, set {} to protect the editor",
+            style("--password").yellow()
+        );
+    }
+
     let port = config.port;
     let addr = SocketAddr::from(([0, 0, 0, 0], port));
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
@@ -108,5 +143,35 @@ async fn main() {
         "Press {} to stop the server",
         style("Ctrl + C").green().bold()
     );
-    axum::serve(listener, app).await.unwrap();
+    // the peer address keys the sign-in throttle
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
+}
+
+fn cors_layer(config: &Config) -> CorsLayer {
+    let origins = &config.cors_origins;
+    if origins.iter().any(|origin| origin == "*") {
+        return CorsLayer::new().allow_origin(Any);
+    }
+
+    let origins: Vec<HeaderValue> = origins
+        .iter()
+        .filter_map(|origin| match HeaderValue::from_str(origin) {
+            Ok(origin) => Some(origin),
+            Err(_) => {
+                warn!("Ignoring invalid CORS origin: {origin}");
+                None
+            }
+        })
+        .collect();
+    CorsLayer::new()
+        .allow_origin(AllowOrigin::list(origins))
+        .allow_methods(AllowMethods::mirror_request())
+        .allow_headers(AllowHeaders::mirror_request())
+        .expose_headers([header::ETAG])
+        .allow_credentials(true)
 }